@@ -0,0 +1,234 @@
+//! Drives random order flow through `TWAPOracle::observe` and checks that a
+//! trader can't push the oracle further than its settings allow.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use openbook_twap::{
+    CreateTWAPMarketArgs, ObservationChangeMode, PostEndTrading, TWAPMarket, TWAPOracle,
+    TWAPWeighting, WideSpreadBehavior,
+};
+
+const INITIAL_SLOT: u64 = 1_000;
+const INITIAL_TIMESTAMP: i64 = 1_700_000_000;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    config: Config,
+    actions: Vec<Action>,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Config {
+    expected_value: u32,
+    max_observation_change_per_update_lots: u32,
+    max_observation_change_per_update_bps: u16,
+    basis_points: bool,
+    max_observation_change_scaling_slots: u8,
+    max_spread_bps: u16,
+    wide_spread_behavior: u8,
+    checkpoint_interval_slots: u8,
+    accumulate_log_observations: bool,
+    start_delay_slots: u8,
+    end_slot_after: Option<u16>,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Action {
+    /// Takes the best ask if it's at or below `price`, or rests a bid
+    Bid {
+        price: u64,
+    },
+    /// Takes the best bid if it's at or above `price`, or rests an ask
+    Ask {
+        price: u64,
+    },
+    /// Cancels a resting bid, picked by `index` modulo the number of bids
+    CancelBid {
+        index: u8,
+    },
+    CancelAsk {
+        index: u8,
+    },
+    /// Cranks the oracle without touching the book
+    Crank,
+    Wait {
+        slots: u16,
+        seconds: u16,
+    },
+}
+
+/// A book of single-lot orders, which never crosses since orders that would
+/// cross it take instead
+#[derive(Default)]
+struct Book {
+    bids: Vec<i64>,
+    asks: Vec<i64>,
+}
+
+impl Book {
+    fn best_bid(&self) -> Option<i64> {
+        self.bids.iter().copied().max()
+    }
+
+    fn best_ask(&self) -> Option<i64> {
+        self.asks.iter().copied().min()
+    }
+
+    fn bid(&mut self, price: i64) {
+        match self.best_ask() {
+            Some(best_ask) if best_ask <= price => remove(&mut self.asks, best_ask),
+            _ => self.bids.push(price),
+        }
+    }
+
+    fn ask(&mut self, price: i64) {
+        match self.best_bid() {
+            Some(best_bid) if best_bid >= price => remove(&mut self.bids, best_bid),
+            _ => self.asks.push(price),
+        }
+    }
+}
+
+fn remove(orders: &mut Vec<i64>, price: i64) {
+    let index = orders.iter().position(|&p| p == price).unwrap();
+    orders.swap_remove(index);
+}
+
+fn cancel(orders: &mut Vec<i64>, index: u8) {
+    if !orders.is_empty() {
+        orders.swap_remove(index as usize % orders.len());
+    }
+}
+
+/// Maps any `u64` to a valid openbook price in lots
+fn price_lots(raw: u64) -> i64 {
+    (raw % i64::MAX as u64) as i64 + 1
+}
+
+/// Records an observation the way the program does before each order
+/// action, and checks the oracle's invariants. `range` holds the lowest and
+/// highest observations aggregated so far.
+fn observe(
+    oracle: &mut TWAPOracle,
+    book: &Book,
+    slot: u64,
+    unix_ts: i64,
+    range: &mut Option<(u64, u64)>,
+) {
+    let last_observation = oracle.last_observation;
+    let last_aggregator = oracle.observation_aggregator;
+    let was_warmed_up = oracle.is_warmed_up();
+    let max_change = oracle.max_observation_change(slot.saturating_sub(oracle.last_updated_slot));
+
+    let recorded = oracle.observe(slot, unix_ts, book.best_bid(), book.best_ask());
+
+    assert!(
+        oracle.observation_aggregator >= last_aggregator,
+        "the aggregator decreased"
+    );
+
+    if let Ok(recorded) = recorded {
+        let observation = recorded.clamped_observation;
+        let change = observation.abs_diff(last_observation);
+
+        assert!(change <= max_change, "moved by {} > {}", change, max_change);
+        if oracle.observation_change_mode == ObservationChangeMode::Lots
+            && oracle.max_observation_change_scaling_slots == 0
+        {
+            assert!(change <= oracle.max_observation_change_per_update_lots);
+        }
+
+        if oracle.is_warmed_up() {
+            *range = match *range {
+                Some((min, max)) if was_warmed_up => {
+                    Some((min.min(observation), max.max(observation)))
+                }
+                // aggregation restarted with this observation
+                _ => Some((observation, observation)),
+            };
+        }
+    }
+
+    let Some((min, max)) = *range else {
+        assert!(!oracle.is_warmed_up());
+        return;
+    };
+
+    let in_range = |twap: u64| min <= twap && twap <= max;
+
+    let twap = oracle.twap(TWAPWeighting::Slot, None, slot).unwrap();
+    assert!(in_range(twap), "TWAP {} outside [{}, {}]", twap, min, max);
+
+    let now = unix_ts.max(oracle.last_updated_timestamp) as u64;
+    let twap = oracle.twap(TWAPWeighting::Time, None, now).unwrap();
+    assert!(in_range(twap), "TWAP {} outside [{}, {}]", twap, min, max);
+
+    let oldest = oracle.checkpoints().next().unwrap();
+    let newest = oracle.latest_checkpoint();
+    if newest.slot > oldest.slot {
+        let twap = TWAPMarket::twap_between(oldest, newest).unwrap();
+        assert!(in_range(twap), "TWAP {} outside [{}, {}]", twap, min, max);
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let config = input.config;
+
+    let args = CreateTWAPMarketArgs {
+        expected_value: config.expected_value as u64,
+        max_observation_change_per_update_lots: config.max_observation_change_per_update_lots
+            as u64,
+        max_observation_change_per_update_bps: (config.max_observation_change_per_update_bps
+            % 10_000) as u64,
+        observation_change_mode: if config.basis_points {
+            ObservationChangeMode::BasisPoints
+        } else {
+            ObservationChangeMode::Lots
+        },
+        max_observation_change_scaling_slots: config.max_observation_change_scaling_slots as u64,
+        max_spread_bps: config.max_spread_bps as u64,
+        wide_spread_behavior: match config.wide_spread_behavior % 3 {
+            0 => WideSpreadBehavior::Skip,
+            1 => WideSpreadBehavior::ClampTowardLastObservation,
+            _ => WideSpreadBehavior::DampenTowardMidpoint,
+        },
+        checkpoint_interval_slots: config.checkpoint_interval_slots as u64,
+        accumulate_log_observations: config.accumulate_log_observations,
+        start_delay_slots: config.start_delay_slots as u64,
+        end_slot: config
+            .end_slot_after
+            .map(|after| INITIAL_SLOT + config.start_delay_slots as u64 + after as u64),
+        post_end_trading: PostEndTrading::Continue,
+    };
+
+    let mut oracle = TWAPOracle::new_at(&args, INITIAL_SLOT, INITIAL_TIMESTAMP);
+    let mut book = Book::default();
+
+    let (mut slot, mut unix_ts) = (INITIAL_SLOT, INITIAL_TIMESTAMP);
+    let mut range = if oracle.is_warmed_up() {
+        Some((args.expected_value, args.expected_value))
+    } else {
+        None
+    };
+
+    for action in input.actions {
+        if let Action::Wait { slots, seconds } = action {
+            slot += slots as u64;
+            unix_ts += seconds as i64;
+            continue;
+        }
+
+        // every instruction that touches the book observes it first
+        observe(&mut oracle, &book, slot, unix_ts, &mut range);
+
+        match action {
+            Action::Bid { price } => book.bid(price_lots(price)),
+            Action::Ask { price } => book.ask(price_lots(price)),
+            Action::CancelBid { index } => cancel(&mut book.bids, index),
+            Action::CancelAsk { index } => cancel(&mut book.asks, index),
+            Action::Crank | Action::Wait { .. } => {}
+        }
+    }
+});
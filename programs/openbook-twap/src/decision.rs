@@ -0,0 +1,357 @@
+use anchor_lang::prelude::*;
+
+use crate::*;
+
+/// Links the pass and fail markets of a futarchy proposal
+#[account]
+pub struct DecisionPair {
+    pub authority: Pubkey,
+    pub pass_twap_market: Pubkey,
+    pub fail_twap_market: Pubkey,
+    pub start_slot: u64,
+    pub end_slot: u64,
+    pub pass_threshold_bps: u64,
+    pub outcome: DecisionOutcome,
+    pub pass_twap: Option<u64>,
+    pub fail_twap: Option<u64>,
+    pub pda_bump: u8,
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+#[repr(u8)]
+pub enum DecisionOutcome {
+    Pending = 0,
+    Pass = 1,
+    Fail = 2,
+    Undecided = 3,
+}
+
+impl DecisionPair {
+    pub const SIZE: usize = 32 + 32 + 32 + 8 + 8 + 8 + 1 + (1 + 8) + (1 + 8) + 1;
+
+    /// The window must end with the market and start within `MAX_CHECKPOINTS - 1`
+    /// intervals of the end, so that its TWAP can't change once it has passed
+    pub fn check_window(oracle: &TWAPOracle, start_slot: u64, end_slot: u64) -> Result<()> {
+        require!(
+            end_slot > start_slot,
+            OpenBookTWAPError::InvalidDecisionWindow
+        );
+        // the initial slot only moves until the warm-up is over
+        require!(
+            oracle.is_warmed_up() && oracle.initial_slot <= start_slot,
+            OpenBookTWAPError::InvalidDecisionWindow
+        );
+
+        // an earlier end could be back-filled by the next observation after
+        // it, so the outcome would depend on whether a crank came first
+        require!(
+            oracle.end_slot == Some(end_slot),
+            OpenBookTWAPError::InvalidDecisionWindow
+        );
+
+        let history_slots = std::cmp::max(oracle.checkpoint_interval_slots, 1)
+            .saturating_mul(MAX_CHECKPOINTS as u64 - 1);
+        require!(
+            end_slot - start_slot <= history_slots,
+            OpenBookTWAPError::InvalidDecisionWindow
+        );
+
+        Ok(())
+    }
+
+    pub fn outcome(pass_twap: u64, fail_twap: u64, pass_threshold_bps: u64) -> DecisionOutcome {
+        // whether `a` exceeds `b` by more than the threshold, without division
+        let exceeds = |a: u64, b: u64| {
+            a as u128 * 10_000 > (b as u128).saturating_mul(10_000 + pass_threshold_bps as u128)
+        };
+
+        if exceeds(pass_twap, fail_twap) {
+            DecisionOutcome::Pass
+        } else if exceeds(fail_twap, pass_twap) {
+            DecisionOutcome::Fail
+        } else {
+            DecisionOutcome::Undecided
+        }
+    }
+
+    pub fn resolve(
+        &mut self,
+        pass_oracle: &TWAPOracle,
+        fail_oracle: &TWAPOracle,
+        current_slot: u64,
+    ) -> Result<DecisionOutcome> {
+        require!(
+            self.outcome == DecisionOutcome::Pending,
+            OpenBookTWAPError::DecisionAlreadyResolved
+        );
+        require!(
+            current_slot > self.end_slot,
+            OpenBookTWAPError::DecisionWindowNotOver
+        );
+
+        let pass_twap =
+            pass_oracle.twap(TWAPWeighting::Slot, Some(self.start_slot), self.end_slot)?;
+        let fail_twap =
+            fail_oracle.twap(TWAPWeighting::Slot, Some(self.start_slot), self.end_slot)?;
+
+        self.pass_twap = Some(pass_twap);
+        self.fail_twap = Some(fail_twap);
+        self.outcome = Self::outcome(pass_twap, fail_twap, self.pass_threshold_bps);
+
+        Ok(self.outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_decision_pair_size() {
+        let decision_pair = DecisionPair {
+            authority: Pubkey::new_unique(),
+            pass_twap_market: Pubkey::new_unique(),
+            fail_twap_market: Pubkey::new_unique(),
+            start_slot: 10,
+            end_slot: 20,
+            pass_threshold_bps: 1_000,
+            outcome: DecisionOutcome::Pass,
+            pass_twap: Some(300),
+            fail_twap: Some(200),
+            pda_bump: 255,
+        };
+        assert_eq!(
+            DecisionPair::SIZE,
+            decision_pair.try_to_vec().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_decision_outcome() {
+        // a 10% threshold
+        assert_eq!(
+            DecisionPair::outcome(111, 100, 1_000),
+            DecisionOutcome::Pass
+        );
+        assert_eq!(
+            DecisionPair::outcome(110, 100, 1_000),
+            DecisionOutcome::Undecided
+        );
+        assert_eq!(
+            DecisionPair::outcome(100, 100, 1_000),
+            DecisionOutcome::Undecided
+        );
+        assert_eq!(
+            DecisionPair::outcome(100, 110, 1_000),
+            DecisionOutcome::Undecided
+        );
+        assert_eq!(
+            DecisionPair::outcome(100, 111, 1_000),
+            DecisionOutcome::Fail
+        );
+
+        // without a threshold, any difference decides
+        assert_eq!(DecisionPair::outcome(101, 100, 0), DecisionOutcome::Pass);
+        assert_eq!(
+            DecisionPair::outcome(100, 100, 0),
+            DecisionOutcome::Undecided
+        );
+        assert_eq!(DecisionPair::outcome(100, 101, 0), DecisionOutcome::Fail);
+
+        // no overflow at the extremes
+        assert_eq!(
+            DecisionPair::outcome(u64::MAX, u64::MAX - 1, u64::MAX),
+            DecisionOutcome::Undecided
+        );
+    }
+
+    #[test]
+    fn test_resolve_decision() {
+        let mut pass_oracle = new_oracle(100, ObservationChangeMode::Lots);
+        pass_oracle.push_checkpoint();
+        record(&mut pass_oracle, 10, 10, 200);
+        record(&mut pass_oracle, 20, 20, 300);
+
+        let mut fail_oracle = new_oracle(100, ObservationChangeMode::Lots);
+        fail_oracle.push_checkpoint();
+        record(&mut fail_oracle, 20, 20, 200);
+
+        let mut decision_pair = DecisionPair {
+            authority: Pubkey::new_unique(),
+            pass_twap_market: Pubkey::new_unique(),
+            fail_twap_market: Pubkey::new_unique(),
+            start_slot: 10,
+            end_slot: 20,
+            pass_threshold_bps: 1_000,
+            outcome: DecisionOutcome::Pending,
+            pass_twap: None,
+            fail_twap: None,
+            pda_bump: 255,
+        };
+
+        assert_eq!(
+            decision_pair
+                .resolve(&pass_oracle, &fail_oracle, 20)
+                .unwrap_err(),
+            error!(OpenBookTWAPError::DecisionWindowNotOver)
+        );
+
+        assert_eq!(
+            decision_pair
+                .resolve(&pass_oracle, &fail_oracle, 21)
+                .unwrap(),
+            DecisionOutcome::Pass
+        );
+        assert_eq!(decision_pair.pass_twap, Some(300));
+        assert_eq!(decision_pair.fail_twap, Some(200));
+
+        assert_eq!(
+            decision_pair
+                .resolve(&pass_oracle, &fail_oracle, 22)
+                .unwrap_err(),
+            error!(OpenBookTWAPError::DecisionAlreadyResolved)
+        );
+    }
+
+    #[test]
+    fn test_decision_check_window() {
+        let args = CreateTWAPMarketArgs {
+            checkpoint_interval_slots: 10,
+            end_slot: Some(300),
+            ..new_args(100)
+        };
+        let oracle = TWAPOracle::new_at(&args, 50, 0);
+
+        assert!(DecisionPair::check_window(&oracle, 50, 300).is_ok());
+        assert!(DecisionPair::check_window(&oracle, 100, 300).is_ok());
+
+        for (start_slot, end_slot) in [
+            // empty
+            (300, 300),
+            // before the market started
+            (49, 300),
+            // before the market ends
+            (100, 200),
+            // after the market ends
+            (100, 301),
+        ] {
+            assert_eq!(
+                DecisionPair::check_window(&oracle, start_slot, end_slot).unwrap_err(),
+                error!(OpenBookTWAPError::InvalidDecisionWindow)
+            );
+        }
+
+        // 31 intervals of history reach back from slot 400 to slot 90
+        let oracle = TWAPOracle::new_at(
+            &CreateTWAPMarketArgs {
+                end_slot: Some(400),
+                ..args
+            },
+            50,
+            0,
+        );
+        assert!(DecisionPair::check_window(&oracle, 90, 400).is_ok());
+        assert_eq!(
+            DecisionPair::check_window(&oracle, 89, 400).unwrap_err(),
+            error!(OpenBookTWAPError::InvalidDecisionWindow)
+        );
+
+        // without an end, history can always be overwritten
+        let oracle = TWAPOracle::new_at(
+            &CreateTWAPMarketArgs {
+                end_slot: None,
+                ..args
+            },
+            50,
+            0,
+        );
+        assert_eq!(
+            DecisionPair::check_window(&oracle, 100, 200).unwrap_err(),
+            error!(OpenBookTWAPError::InvalidDecisionWindow)
+        );
+
+        // the initial slot may still move during a warm-up
+        let oracle = TWAPOracle::new_at(
+            &CreateTWAPMarketArgs {
+                start_delay_slots: 10,
+                ..args
+            },
+            50,
+            0,
+        );
+        assert_eq!(
+            DecisionPair::check_window(&oracle, 100, 300).unwrap_err(),
+            error!(OpenBookTWAPError::InvalidDecisionWindow)
+        );
+    }
+
+    #[test]
+    fn test_decision_ignores_cranks_after_the_window() {
+        let mut oracle = TWAPOracle::new_at(
+            &CreateTWAPMarketArgs {
+                end_slot: Some(20),
+                ..new_args(100)
+            },
+            0,
+            0,
+        );
+        oracle.observe(10, 10, Some(150), Some(150)).unwrap();
+
+        // (150 * 15) / 15, extrapolating the last observation
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(0), 15).unwrap(), 150);
+
+        // a crank after a window that ends before the market does back-fills
+        // it, (150 * 10 + 300 * 5) / 15
+        oracle.observe(16, 16, Some(300), Some(300)).unwrap();
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(0), 15).unwrap(), 200);
+        assert_eq!(
+            DecisionPair::check_window(&oracle, 0, 15).unwrap_err(),
+            error!(OpenBookTWAPError::InvalidDecisionWindow)
+        );
+
+        // while after the market's end, cranks are skipped
+        DecisionPair::check_window(&oracle, 0, 20).unwrap();
+        let twap_at_end = oracle.twap(TWAPWeighting::Slot, Some(0), 20).unwrap();
+        assert_eq!(
+            oracle.observe(21, 21, Some(1_000), Some(1_000)),
+            Err(SkipReason::Ended)
+        );
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, Some(0), 20).unwrap(),
+            twap_at_end
+        );
+    }
+
+    #[test]
+    fn test_decision_window_survives_history() {
+        // a window as long as `check_window` allows, with an observation in
+        // every slot, so that a checkpoint is written every interval
+        let mut oracle = TWAPOracle::new_at(
+            &CreateTWAPMarketArgs {
+                checkpoint_interval_slots: 3,
+                end_slot: Some(3 * (MAX_CHECKPOINTS as u64 - 1)),
+                ..new_args(100)
+            },
+            0,
+            0,
+        );
+        DecisionPair::check_window(&oracle, 0, 3 * (MAX_CHECKPOINTS as u64 - 1)).unwrap();
+
+        for slot in 1..=200 {
+            let _ = oracle.observe(slot, slot as i64, Some(100), Some(100));
+        }
+        assert_eq!(oracle.num_checkpoints, MAX_CHECKPOINTS as u64);
+
+        assert_eq!(
+            oracle
+                .twap(
+                    TWAPWeighting::Slot,
+                    Some(0),
+                    3 * (MAX_CHECKPOINTS as u64 - 1)
+                )
+                .unwrap(),
+            100
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use anchor_lang::prelude::*;
+
+#[error_code]
+pub enum OpenBookTWAPError {
+    #[msg(
+        "The `open_orders_admin` of the underlying market must be equal to the `TWAPMarket` PDA"
+    )]
+    InvalidOpenOrdersAdmin,
+    #[msg(
+        "The `close_market_admin` of the underlying market must be equal to the `TWAPMarket` PDA"
+    )]
+    InvalidCloseMarketAdmin,
+    #[msg(
+        "Oracle-pegged trades mess up the TWAP so oracles and oracle-pegged trades aren't allowed"
+    )]
+    NoOracles,
+    #[msg("Maker fee must be zero")]
+    InvalidMakerFee,
+    #[msg("Taker fee must be zero")]
+    InvalidTakerFee,
+    #[msg("Seq num must be zero")]
+    InvalidSeqNum,
+    #[msg("Consume events admin must be None")]
+    InvalidConsumeEventsAdmin,
+    #[msg("The TWAP window must end after it starts")]
+    EmptyTWAPWindow,
+    #[msg("The TWAP window can't start before the market was created")]
+    TWAPWindowPrecedesMarket,
+    #[msg("The TWAP window starts before the oldest stored checkpoint")]
+    TWAPWindowOutOfRange,
+    #[msg("The TWAP window can't end in the future")]
+    TWAPWindowInFuture,
+    #[msg("Overflow in TWAP math")]
+    MathOverflow,
+    #[msg("The `TWAPMarket` doesn't wrap the passed market")]
+    InvalidMarket,
+    #[msg("The passed bids account isn't the market's bids")]
+    InvalidBids,
+    #[msg("The passed asks account isn't the market's asks")]
+    InvalidAsks,
+    #[msg("Expected value must be non-zero when observation changes are limited in basis points")]
+    InvalidExpectedValue,
+    #[msg("The TWAP is still warming up")]
+    TWAPNotStarted,
+    #[msg("End slot must not be before the warm-up is over")]
+    InvalidEndSlot,
+    #[msg("This TWAP market has no end slot")]
+    TWAPHasNoEndSlot,
+    #[msg("This TWAP hasn't ended yet")]
+    TWAPNotEnded,
+    #[msg("This TWAP has already been finalized")]
+    TWAPAlreadyFinalized,
+    #[msg("This TWAP has ended and the market no longer accepts orders")]
+    TWAPMarketEnded,
+    #[msg("The close authority didn't sign")]
+    InvalidCloseAuthority,
+    #[msg("This TWAP market already has the latest layout")]
+    TWAPMarketAlreadyMigrated,
+    #[msg("A decision needs two distinct TWAP markets, and the passed markets must be its own")]
+    InvalidDecisionPair,
+    #[msg("A decision's window must end with both markets, and start within their aggregation and history")]
+    InvalidDecisionWindow,
+    #[msg("This decision's window isn't over yet")]
+    DecisionWindowNotOver,
+    #[msg("This decision has already been resolved")]
+    DecisionAlreadyResolved,
+    #[msg("Checkpoint interval must be at least `MIN_CHECKPOINT_INTERVAL_SLOTS`")]
+    InvalidCheckpointInterval,
+    #[msg("This TWAP has an end slot and must be finalized first")]
+    TWAPNotFinalized,
+    #[msg("The open orders account isn't the owner's next one")]
+    InvalidOpenOrdersAccount,
+    #[msg("A decision's pass threshold can't exceed 10,000 basis points")]
+    InvalidPassThreshold,
+    #[msg("This TWAP market has decisions that haven't been resolved")]
+    UnresolvedDecisions,
+    #[msg("These checkpoints don't come from the same TWAP oracle")]
+    InvalidTWAPWindow,
+    #[msg("This decision hasn't been resolved yet")]
+    DecisionNotResolved,
+    #[msg("Max observation change per update must be below 10,000 bps, and above 0 in `BasisPoints` mode")]
+    InvalidMaxObservationChangeBps,
+}
@@ -0,0 +1,142 @@
+use anchor_lang::prelude::*;
+
+use crate::*;
+
+#[event]
+pub struct TWAPObservation {
+    pub twap_market: Pubkey,
+    pub slot: u64,
+    pub unix_ts: i64,
+    pub best_bid: i64,
+    pub best_ask: i64,
+    pub spot_price: u64,
+    pub clamped_observation: u64,
+    pub weighted_observation: u128,
+    pub aggregator: u128,
+}
+
+#[event]
+pub struct TWAPObservationSkipped {
+    pub twap_market: Pubkey,
+    pub slot: u64,
+    pub unix_ts: i64,
+    pub best_bid: Option<i64>,
+    pub best_ask: Option<i64>,
+    pub reason: SkipReason,
+}
+
+/// Emitted by each instruction that is proxied to openbook
+#[event]
+pub struct TWAPMarketAction {
+    pub twap_market: Pubkey,
+    /// `None` for permissionless actions
+    pub signer: Option<Pubkey>,
+    pub open_orders_account: Option<Pubkey>,
+    pub action: MarketAction,
+    pub order_ids: Vec<Option<u128>>,
+    pub cancelled_size: Option<i64>,
+    pub order: Option<OrderSummary>,
+}
+
+impl TWAPMarketAction {
+    pub fn new(
+        twap_market: Pubkey,
+        signer: Option<Pubkey>,
+        open_orders_account: Option<Pubkey>,
+        action: MarketAction,
+    ) -> Self {
+        Self {
+            twap_market,
+            signer,
+            open_orders_account,
+            action,
+            order_ids: vec![],
+            cancelled_size: None,
+            order: None,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct OrderSummary {
+    pub side: Side,
+    pub price_lots: i64,
+    pub max_base_lots: i64,
+    pub max_quote_lots_including_fees: i64,
+}
+
+impl From<PlaceOrderArgs> for OrderSummary {
+    fn from(args: PlaceOrderArgs) -> Self {
+        Self {
+            side: args.side,
+            price_lots: args.price_lots,
+            max_base_lots: args.max_base_lots,
+            max_quote_lots_including_fees: args.max_quote_lots_including_fees,
+        }
+    }
+}
+
+impl From<PlaceTakeOrderArgs> for OrderSummary {
+    fn from(args: PlaceTakeOrderArgs) -> Self {
+        Self {
+            side: args.side,
+            price_lots: args.price_lots,
+            max_base_lots: args.max_base_lots,
+            max_quote_lots_including_fees: args.max_quote_lots_including_fees,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+#[repr(u8)]
+pub enum MarketAction {
+    PlaceOrder = 0,
+    EditOrder = 1,
+    CancelOrderByClientId = 2,
+    CancelAllOrders = 3,
+    PlaceTakeOrder = 4,
+    CancelAndPlaceOrders = 5,
+    PruneOrders = 6,
+    SettleFundsExpired = 7,
+    CloseMarket = 8,
+    CreateOpenOrdersAccount = 9,
+    CloseOpenOrdersAccount = 10,
+    Deposit = 11,
+    Refill = 12,
+    SettleFunds = 13,
+    ConsumeEvents = 14,
+}
+
+#[event]
+pub struct TWAPMarketClosed {
+    pub twap_market: Pubkey,
+    pub market: Pubkey,
+    pub initial_slot: u64,
+    pub last_updated_slot: u64,
+    pub last_observation: u64,
+    pub observation_aggregator: u128,
+    pub twap: Option<u64>,
+    pub final_twap: Option<u64>,
+}
+
+#[event]
+pub struct TWAPMarketMigrated {
+    pub twap_market: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+}
+
+#[event]
+pub struct DecisionResolved {
+    pub decision_pair: Pubkey,
+    pub pass_twap: u64,
+    pub fail_twap: u64,
+    pub outcome: DecisionOutcome,
+}
+
+#[event]
+pub struct TWAPFinalized {
+    pub twap_market: Pubkey,
+    pub end_slot: u64,
+    pub final_twap: u64,
+}
@@ -0,0 +1,674 @@
+use anchor_lang::prelude::*;
+use openbook_v2::program::OpenbookV2;
+use openbook_v2::state::{BookSide, Market, OpenOrdersAccount};
+
+use crate::*;
+
+#[derive(Accounts)]
+pub struct CreateTWAPMarket<'info> {
+    pub market: AccountLoader<'info, Market>,
+    #[account(
+        init,
+        payer = payer,
+        space = TWAP_MARKET_SPACE,
+        seeds = [b"twap_market", market.key().as_ref()],
+        bump
+    )]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    pub system_program: Program<'info, System>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateMarketWithTWAP<'info> {
+    /// A new keypair, which must sign for openbook's `create_market`
+    #[account(mut)]
+    pub market: Signer<'info>,
+    #[account(
+        init,
+        payer = payer,
+        space = TWAP_MARKET_SPACE,
+        seeds = [b"twap_market", market.key().as_ref()],
+        bump
+    )]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    /// CHECK: verified in CPI
+    pub market_authority: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub bids: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub asks: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub event_heap: UncheckedAccount<'info>,
+    /// CHECK: initialized in CPI
+    #[account(mut)]
+    pub market_base_vault: UncheckedAccount<'info>,
+    /// CHECK: initialized in CPI
+    #[account(mut)]
+    pub market_quote_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub base_mint: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub quote_mint: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub event_authority: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: verified in CPI
+    pub token_program: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub associated_token_program: UncheckedAccount<'info>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateOpenOrdersAccount<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub owner: Signer<'info>,
+    /// CHECK: created in CPI if the owner doesn't have one yet
+    #[account(
+        mut,
+        seeds = [b"OpenOrdersIndexer", owner.key().as_ref()],
+        bump,
+        seeds::program = openbook_program.key()
+    )]
+    pub open_orders_indexer: UncheckedAccount<'info>,
+    /// CHECK: initialized in CPI, and its address is checked in the handler
+    #[account(mut)]
+    pub open_orders_account: UncheckedAccount<'info>,
+    #[account(has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    pub market: AccountLoader<'info, Market>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseOpenOrdersAccount<'info> {
+    pub owner: Signer<'info>,
+    /// CHECK: verified in CPI
+    #[account(
+        mut,
+        seeds = [b"OpenOrdersIndexer", owner.key().as_ref()],
+        bump,
+        seeds::program = openbook_program.key()
+    )]
+    pub open_orders_indexer: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"OpenOrders",
+            owner.key().as_ref(),
+            &open_orders_account.load()?.account_num.to_le_bytes()
+        ],
+        bump = open_orders_account.load()?.bump,
+        seeds::program = openbook_program.key(),
+        constraint = open_orders_account.load()?.market == twap_market.market
+            @ OpenBookTWAPError::InvalidMarket
+    )]
+    pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+    /// CHECK: only receives lamports
+    #[account(mut)]
+    pub sol_destination: UncheckedAccount<'info>,
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceOrder<'info> {
+    pub signer: Signer<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub open_orders_account: UncheckedAccount<'info>,
+    #[account(mut, has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_token_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        has_one = bids @ OpenBookTWAPError::InvalidBids,
+        has_one = asks @ OpenBookTWAPError::InvalidAsks
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub event_heap: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub token_program: UncheckedAccount<'info>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOrder<'info> {
+    pub signer: Signer<'info>,
+    #[account(mut, has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub open_orders_account: UncheckedAccount<'info>,
+    #[account(
+        has_one = bids @ OpenBookTWAPError::InvalidBids,
+        has_one = asks @ OpenBookTWAPError::InvalidAsks
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+}
+
+#[derive(Accounts)]
+pub struct PruneOrders<'info> {
+    #[account(has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub open_orders_account: UncheckedAccount<'info>,
+    #[account(
+        has_one = bids @ OpenBookTWAPError::InvalidBids,
+        has_one = asks @ OpenBookTWAPError::InvalidAsks
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+}
+
+#[derive(Accounts)]
+pub struct SettleFundsExpired<'info> {
+    #[account(mut, has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub open_orders_account: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+    /// CHECK: verified in CPI
+    pub market_authority: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_base_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_quote_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_base_account: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_quote_account: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub token_program: UncheckedAccount<'info>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseMarket<'info> {
+    /// CHECK: only receives lamports, and must match the TWAP market's
+    #[account(mut)]
+    pub close_market_rent_receiver: UncheckedAccount<'info>,
+    /// Required when the TWAP market has a close authority
+    pub close_authority: Option<Signer<'info>>,
+    #[account(
+        mut,
+        close = close_market_rent_receiver,
+        has_one = close_market_rent_receiver,
+        has_one = market @ OpenBookTWAPError::InvalidMarket
+    )]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    #[account(
+        mut,
+        has_one = bids @ OpenBookTWAPError::InvalidBids,
+        has_one = asks @ OpenBookTWAPError::InvalidAsks
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub event_heap: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub token_program: UncheckedAccount<'info>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateTWAPMarket<'info> {
+    /// CHECK: the discriminator is checked in the handler
+    #[account(mut, owner = crate::ID)]
+    pub twap_market: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetCloseAuthority<'info> {
+    #[account(
+        mut,
+        constraint = twap_market.close_authority == Some(close_authority.key())
+            @ OpenBookTWAPError::InvalidCloseAuthority
+    )]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    pub close_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceTakeOrder<'info> {
+    #[account(mut, has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        has_one = bids @ OpenBookTWAPError::InvalidBids,
+        has_one = asks @ OpenBookTWAPError::InvalidAsks
+    )]
+    pub market: AccountLoader<'info, Market>,
+    /// CHECK: verified in CPI
+    pub market_authority: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_base_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_quote_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub event_heap: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_base_account: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_quote_account: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub token_program: UncheckedAccount<'info>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelAndPlaceOrders<'info> {
+    pub signer: Signer<'info>,
+    #[account(mut, has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub open_orders_account: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_quote_account: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_base_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        has_one = bids @ OpenBookTWAPError::InvalidBids,
+        has_one = asks @ OpenBookTWAPError::InvalidAsks
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub event_heap: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_quote_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_base_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub token_program: UncheckedAccount<'info>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    pub owner: Signer<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_base_account: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_quote_account: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub open_orders_account: UncheckedAccount<'info>,
+    #[account(mut, has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+    /// Optional, and only used to record an observation
+    #[account(address = market.load()?.bids @ OpenBookTWAPError::InvalidBids)]
+    pub bids: Option<AccountLoader<'info, BookSide>>,
+    /// Optional, and only used to record an observation
+    #[account(address = market.load()?.asks @ OpenBookTWAPError::InvalidAsks)]
+    pub asks: Option<AccountLoader<'info, BookSide>>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_base_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_quote_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub token_program: UncheckedAccount<'info>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+}
+
+#[derive(Accounts)]
+pub struct SettleFunds<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub open_orders_account: UncheckedAccount<'info>,
+    #[account(mut, has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+    /// Optional, and only used to record an observation
+    #[account(address = market.load()?.bids @ OpenBookTWAPError::InvalidBids)]
+    pub bids: Option<AccountLoader<'info, BookSide>>,
+    /// Optional, and only used to record an observation
+    #[account(address = market.load()?.asks @ OpenBookTWAPError::InvalidAsks)]
+    pub asks: Option<AccountLoader<'info, BookSide>>,
+    /// CHECK: verified in CPI
+    pub market_authority: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_base_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub market_quote_vault: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_base_account: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub user_quote_account: UncheckedAccount<'info>,
+    /// CHECK: verified in CPI
+    pub token_program: UncheckedAccount<'info>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ConsumeEvents<'info> {
+    #[account(mut, has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    #[account(
+        mut,
+        has_one = bids @ OpenBookTWAPError::InvalidBids,
+        has_one = asks @ OpenBookTWAPError::InvalidAsks
+    )]
+    pub market: AccountLoader<'info, Market>,
+    pub bids: AccountLoader<'info, BookSide>,
+    pub asks: AccountLoader<'info, BookSide>,
+    /// CHECK: verified in CPI
+    #[account(mut)]
+    pub event_heap: UncheckedAccount<'info>,
+    pub openbook_program: Program<'info, OpenbookV2>,
+}
+
+pub(crate) fn update_oracle_if_book_passed<'info>(
+    twap_market: &mut Account<'info, TWAPMarket>,
+    bids: &Option<AccountLoader<'info, BookSide>>,
+    asks: &Option<AccountLoader<'info, BookSide>>,
+) -> Result<()> {
+    if let (Some(bids), Some(asks)) = (bids, asks) {
+        let twap_market_key = twap_market.key();
+
+        twap_market
+            .twap_oracle
+            .update_oracle(twap_market_key, bids.load()?, asks.load()?);
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct GetBestBidAndAsk<'info> {
+    #[account(
+        has_one = bids @ OpenBookTWAPError::InvalidBids,
+        has_one = asks @ OpenBookTWAPError::InvalidAsks
+    )]
+    pub market: AccountLoader<'info, Market>,
+    pub bids: AccountLoader<'info, BookSide>,
+    pub asks: AccountLoader<'info, BookSide>,
+}
+
+#[derive(Accounts)]
+pub struct CrankTWAP<'info> {
+    #[account(mut, has_one = market @ OpenBookTWAPError::InvalidMarket)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+    #[account(
+        has_one = bids @ OpenBookTWAPError::InvalidBids,
+        has_one = asks @ OpenBookTWAPError::InvalidAsks
+    )]
+    pub market: AccountLoader<'info, Market>,
+    pub bids: AccountLoader<'info, BookSide>,
+    pub asks: AccountLoader<'info, BookSide>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeTWAP<'info> {
+    #[account(mut)]
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+}
+
+#[derive(Accounts)]
+pub struct CreateDecisionPair<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + DecisionPair::SIZE,
+        seeds = [
+            b"decision_pair",
+            authority.key().as_ref(),
+            pass_twap_market.key().as_ref(),
+            fail_twap_market.key().as_ref()
+        ],
+        bump
+    )]
+    pub decision_pair: Box<Account<'info, DecisionPair>>,
+    // decisions keep their markets from being closed, so only the markets'
+    // close authority can create them
+    #[account(
+        mut,
+        constraint = pass_twap_market.key() != fail_twap_market.key()
+            @ OpenBookTWAPError::InvalidDecisionPair,
+        constraint = pass_twap_market.close_authority == Some(authority.key())
+            @ OpenBookTWAPError::InvalidCloseAuthority
+    )]
+    pub pass_twap_market: Box<Account<'info, TWAPMarket>>,
+    #[account(
+        mut,
+        constraint = fail_twap_market.close_authority == Some(authority.key())
+            @ OpenBookTWAPError::InvalidCloseAuthority
+    )]
+    pub fail_twap_market: Box<Account<'info, TWAPMarket>>,
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveDecision<'info> {
+    #[account(
+        mut,
+        has_one = pass_twap_market @ OpenBookTWAPError::InvalidDecisionPair,
+        has_one = fail_twap_market @ OpenBookTWAPError::InvalidDecisionPair
+    )]
+    pub decision_pair: Box<Account<'info, DecisionPair>>,
+    #[account(mut)]
+    pub pass_twap_market: Box<Account<'info, TWAPMarket>>,
+    #[account(mut)]
+    pub fail_twap_market: Box<Account<'info, TWAPMarket>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseDecisionPair<'info> {
+    #[account(mut, has_one = authority, close = authority)]
+    pub decision_pair: Box<Account<'info, DecisionPair>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetTWAP<'info> {
+    pub twap_market: Box<Account<'info, TWAPMarket>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Copy, Clone)]
+pub struct CreateTWAPMarketArgs {
+    pub expected_value: u64,
+    pub max_observation_change_per_update_lots: u64,
+    pub max_observation_change_per_update_bps: u64,
+    pub observation_change_mode: ObservationChangeMode,
+    pub max_observation_change_scaling_slots: u64,
+    pub max_spread_bps: u64,
+    pub wide_spread_behavior: WideSpreadBehavior,
+    pub checkpoint_interval_slots: u64,
+    pub accumulate_log_observations: bool,
+    pub start_delay_slots: u64,
+    pub end_slot: Option<u64>,
+    pub post_end_trading: PostEndTrading,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Copy, Clone)]
+pub struct PlaceOrderArgs {
+    pub side: Side,
+    pub price_lots: i64,
+    pub max_base_lots: i64,
+    pub max_quote_lots_including_fees: i64,
+    pub client_order_id: u64,
+    pub order_type: PlaceOrderType,
+    pub expiry_timestamp: u64,
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub limit: u8,
+}
+
+impl From<PlaceOrderArgs> for openbook_v2::PlaceOrderArgs {
+    fn from(args: PlaceOrderArgs) -> Self {
+        Self {
+            side: args.side.into(),
+            price_lots: args.price_lots,
+            max_base_lots: args.max_base_lots,
+            max_quote_lots_including_fees: args.max_quote_lots_including_fees,
+            client_order_id: args.client_order_id,
+            order_type: args.order_type.into(),
+            expiry_timestamp: args.expiry_timestamp,
+            self_trade_behavior: args.self_trade_behavior.into(),
+            limit: args.limit,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Copy, Clone)]
+pub struct PlaceTakeOrderArgs {
+    pub side: Side,
+    pub price_lots: i64,
+    pub max_base_lots: i64,
+    pub max_quote_lots_including_fees: i64,
+    pub order_type: PlaceOrderType,
+    pub limit: u8,
+}
+
+impl From<PlaceTakeOrderArgs> for openbook_v2::PlaceTakeOrderArgs {
+    fn from(args: PlaceTakeOrderArgs) -> Self {
+        Self {
+            side: args.side.into(),
+            price_lots: args.price_lots,
+            max_base_lots: args.max_base_lots,
+            max_quote_lots_including_fees: args.max_quote_lots_including_fees,
+            order_type: args.order_type.into(),
+            limit: args.limit,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+#[repr(u8)]
+pub enum SelfTradeBehavior {
+    DecrementTake = 0,
+    CancelProvide = 1,
+    AbortTransaction = 2,
+}
+
+impl From<SelfTradeBehavior> for openbook_v2::state::SelfTradeBehavior {
+    fn from(behavior: SelfTradeBehavior) -> Self {
+        match behavior {
+            SelfTradeBehavior::DecrementTake => Self::DecrementTake,
+            SelfTradeBehavior::CancelProvide => Self::CancelProvide,
+            SelfTradeBehavior::AbortTransaction => Self::AbortTransaction,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+#[repr(u8)]
+pub enum PlaceOrderType {
+    Limit = 0,
+    ImmediateOrCancel = 1,
+    PostOnly = 2,
+    Market = 3,
+    PostOnlySlide = 4,
+}
+
+impl From<PlaceOrderType> for openbook_v2::state::PlaceOrderType {
+    fn from(order_type: PlaceOrderType) -> Self {
+        match order_type {
+            PlaceOrderType::Limit => Self::Limit,
+            PlaceOrderType::ImmediateOrCancel => Self::ImmediateOrCancel,
+            PlaceOrderType::PostOnly => Self::PostOnly,
+            PlaceOrderType::Market => Self::Market,
+            PlaceOrderType::PostOnlySlide => Self::PostOnlySlide,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+#[repr(u8)]
+pub enum Side {
+    Bid = 0,
+    Ask = 1,
+}
+
+impl From<Side> for openbook_v2::state::Side {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Bid => Self::Bid,
+            Side::Ask => Self::Ask,
+        }
+    }
+}
@@ -1,8 +1,9 @@
+// anchor's `Error` is large, and every instruction returns it
+#![allow(clippy::result_large_err)]
+
 use anchor_lang::prelude::*;
-use num::integer::Average;
-use openbook_v2::program::OpenbookV2;
-use openbook_v2::state::{BookSide, Market};
-use std::cell::Ref;
+use anchor_lang::Discriminator;
+use openbook_v2::state::OpenOrdersIndexer;
 
 #[cfg(not(feature = "no-entrypoint"))]
 use solana_security_txt::security_txt;
@@ -18,414 +19,24 @@ security_txt! {
     auditors: "None"
 }
 
-const TWAP_MARKET: &[u8] = b"twap_market";
-
-declare_id!("twAP5sArq2vDS1mZCT7f4qRLwzTfHvf5Ay5R5Q5df1m");
-
-#[account]
-pub struct TWAPMarket {
-    pub market: Pubkey,
-    pub pda_bump: u8,
-    pub twap_oracle: TWAPOracle,
-    pub close_market_rent_receiver: Pubkey,
-}
-
-impl TWAPMarket {
-    pub fn get_twap_market_seeds<'a>(market: &'a Pubkey, bump: &'a u8) -> [&'a [u8]; 3] {
-        [
-            TWAP_MARKET.as_ref(),
-            market.as_ref(),
-            bytemuck::bytes_of(bump),
-        ]
-    }
-}
-
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
-pub struct TWAPOracle {
-    pub expected_value: u64,
-    pub initial_slot: u64,
-    pub last_updated_slot: u64,
-    pub last_observed_slot: u64,
-    pub last_observation: u64,
-    pub observation_aggregator: u128,
-    pub max_observation_change_per_update_lots: u64,
-}
-
-impl TWAPOracle {
-    pub fn new(expected_value: u64, max_observation_change_per_update_lots: u64) -> Self {
-        // Get the current slot at TWAPOracle initialization
-        // If we cannot get the clock the transaction should fail. Unwise to catch the error.
-        // Starting with a time of 0 (initial solana blockchain slot) messes up later logic in unpredictable ways
-
-        let clock = Clock::get().unwrap();
-        Self {
-            expected_value,
-            initial_slot: clock.slot,
-            last_updated_slot: clock.slot,
-            last_observed_slot: clock.slot,
-            last_observation: expected_value,
-            observation_aggregator: expected_value as u128,
-            max_observation_change_per_update_lots,
-        }
-    }
-
-    pub fn update_oracle(&mut self, bids: Ref<'_, BookSide>, asks: Ref<'_, BookSide>) {
-        let clock = Clock::get().unwrap();
-
-        if self.last_observed_slot < clock.slot {
-            self.last_observed_slot = clock.slot;
-
-            let unix_ts: u64 = clock.unix_timestamp.try_into().unwrap();
-
-            let best_bid = bids.best_price(unix_ts, None);
-            let best_ask = asks.best_price(unix_ts, None);
-
-            if let (Some(best_bid), Some(best_ask)) = (best_bid, best_ask) {
-                // we don't record prices if there's a spread of more than 20%
-                if best_ask > best_bid.saturating_mul(12).saturating_div(10) {
-                    return;
-                }
-
-                // we use average_ceil because (best_bid + best_ask) / 2 can overflow
-                let spot_price = best_bid.average_ceil(&best_ask) as u64;
-
-                let last_observation = self.last_observation;
-
-                let observation = if spot_price > last_observation {
-                    let max_observation = last_observation
-                        .saturating_add(self.max_observation_change_per_update_lots);
-
-                    std::cmp::min(spot_price, max_observation)
-                } else {
-                    let min_observation = last_observation
-                        .saturating_sub(self.max_observation_change_per_update_lots);
-
-                    std::cmp::max(spot_price, min_observation)
-                };
-
-                msg!("Observation: {:?}", observation);
-
-                let weighted_observation =
-                    observation as u128 * (clock.slot - self.last_updated_slot) as u128;
-
-                msg!("Weighted observation: {:?}", weighted_observation);
-
-                self.last_updated_slot = clock.slot;
-                self.last_observation = observation;
-                self.observation_aggregator += weighted_observation;
-            }
-        }
-    }
-}
-
-#[derive(Accounts)]
-pub struct CreateTWAPMarket<'info> {
-    pub market: AccountLoader<'info, Market>,
-    #[account(
-        init,
-        payer = payer,
-        space = 8 + std::mem::size_of::<TWAPMarket>(),
-        seeds = [b"twap_market", market.key().as_ref()],
-        bump
-    )]
-    pub twap_market: Account<'info, TWAPMarket>,
-    pub system_program: Program<'info, System>,
-    #[account(mut)]
-    pub payer: Signer<'info>,
-}
-
-#[derive(Accounts)]
-pub struct PlaceOrder<'info> {
-    pub signer: Signer<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub open_orders_account: UncheckedAccount<'info>,
-    #[account(mut)]
-    pub twap_market: Account<'info, TWAPMarket>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub user_token_account: UncheckedAccount<'info>,
-    #[account(mut)]
-    pub market: AccountLoader<'info, Market>,
-    #[account(mut)]
-    pub bids: AccountLoader<'info, BookSide>,
-    #[account(mut)]
-    pub asks: AccountLoader<'info, BookSide>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub event_heap: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub market_vault: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    pub token_program: UncheckedAccount<'info>,
-    pub openbook_program: Program<'info, OpenbookV2>,
-}
-
-#[derive(Accounts)]
-pub struct CancelOrder<'info> {
-    pub signer: Signer<'info>,
-    #[account(mut)]
-    pub twap_market: Account<'info, TWAPMarket>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub open_orders_account: UncheckedAccount<'info>,
-    pub market: AccountLoader<'info, Market>,
-    #[account(mut)]
-    pub bids: AccountLoader<'info, BookSide>,
-    #[account(mut)]
-    pub asks: AccountLoader<'info, BookSide>,
-    pub openbook_program: Program<'info, OpenbookV2>,
-}
-
-#[derive(Accounts)]
-pub struct PruneOrders<'info> {
-    pub twap_market: Account<'info, TWAPMarket>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub open_orders_account: UncheckedAccount<'info>,
-    pub market: AccountLoader<'info, Market>,
-    #[account(mut)]
-    pub bids: AccountLoader<'info, BookSide>,
-    #[account(mut)]
-    pub asks: AccountLoader<'info, BookSide>,
-    pub openbook_program: Program<'info, OpenbookV2>,
-}
-
-#[derive(Accounts)]
-pub struct SettleFundsExpired<'info> {
-    #[account(mut)]
-    pub twap_market: Account<'info, TWAPMarket>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub open_orders_account: UncheckedAccount<'info>,
-    #[account(mut)]
-    pub market: AccountLoader<'info, Market>,
-    /// CHECK: verified in CPI
-    pub market_authority: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub market_base_vault: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub market_quote_vault: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub user_base_account: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub user_quote_account: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    pub token_program: UncheckedAccount<'info>,
-    pub openbook_program: Program<'info, OpenbookV2>,
-    pub system_program: Program<'info, System>,
-}
-
-#[derive(Accounts)]
-pub struct CloseMarket<'info> {
-    /// CHECK: This is a permissionless function but could be made to require the close_market_rent_receiver's signature
-    #[account(mut)]
-    pub close_market_rent_receiver: UncheckedAccount<'info>,
-    #[account(has_one = close_market_rent_receiver)]
-    pub twap_market: Account<'info, TWAPMarket>,
-    #[account(mut)]
-    pub market: AccountLoader<'info, Market>,
-    #[account(mut)]
-    pub bids: AccountLoader<'info, BookSide>,
-    #[account(mut)]
-    pub asks: AccountLoader<'info, BookSide>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub event_heap: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    pub token_program: UncheckedAccount<'info>,
-    pub openbook_program: Program<'info, OpenbookV2>,
-}
-
-#[derive(Accounts)]
-pub struct PlaceTakeOrder<'info> {
-    #[account(mut)]
-    pub twap_market: Account<'info, TWAPMarket>,
-    #[account(mut)]
-    pub signer: Signer<'info>,
-    #[account(mut)]
-    pub market: AccountLoader<'info, Market>,
-    /// CHECK: verified in CPI
-    pub market_authority: UncheckedAccount<'info>,
-    #[account(mut)]
-    pub bids: AccountLoader<'info, BookSide>,
-    #[account(mut)]
-    pub asks: AccountLoader<'info, BookSide>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub market_base_vault: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub market_quote_vault: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub event_heap: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub user_base_account: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub user_quote_account: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    pub token_program: UncheckedAccount<'info>,
-    pub openbook_program: Program<'info, OpenbookV2>,
-    pub system_program: Program<'info, System>,
-}
-
-#[derive(Accounts)]
-pub struct CancelAndPlaceOrders<'info> {
-    pub signer: Signer<'info>,
-    #[account(mut)]
-    pub twap_market: Account<'info, TWAPMarket>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub open_orders_account: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub user_quote_account: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub user_base_account: UncheckedAccount<'info>,
-    #[account(mut)]
-    pub market: AccountLoader<'info, Market>,
-    #[account(mut)]
-    pub bids: AccountLoader<'info, BookSide>,
-    #[account(mut)]
-    pub asks: AccountLoader<'info, BookSide>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub event_heap: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub market_quote_vault: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    #[account(mut)]
-    pub market_base_vault: UncheckedAccount<'info>,
-    /// CHECK: verified in CPI
-    pub token_program: UncheckedAccount<'info>,
-    pub openbook_program: Program<'info, OpenbookV2>,
-}
-
-#[derive(Accounts)]
-pub struct GetBestBidAndAsk<'info> {
-    #[account(has_one = bids, has_one = asks)]
-    pub market: AccountLoader<'info, Market>,
-    pub bids: AccountLoader<'info, BookSide>,
-    pub asks: AccountLoader<'info, BookSide>,
-}
-
-#[derive(AnchorSerialize, AnchorDeserialize, Debug, Copy, Clone)]
-pub struct PlaceOrderArgs {
-    pub side: Side,
-    pub price_lots: i64,
-    pub max_base_lots: i64,
-    pub max_quote_lots_including_fees: i64,
-    pub client_order_id: u64,
-    pub order_type: PlaceOrderType,
-    pub expiry_timestamp: u64,
-    pub self_trade_behavior: SelfTradeBehavior,
-    pub limit: u8,
-}
-
-impl From<PlaceOrderArgs> for openbook_v2::PlaceOrderArgs {
-    fn from(args: PlaceOrderArgs) -> Self {
-        Self {
-            side: args.side.into(),
-            price_lots: args.price_lots,
-            max_base_lots: args.max_base_lots,
-            max_quote_lots_including_fees: args.max_quote_lots_including_fees,
-            client_order_id: args.client_order_id,
-            order_type: args.order_type.into(),
-            expiry_timestamp: args.expiry_timestamp.into(),
-            self_trade_behavior: args.self_trade_behavior.into(),
-            limit: args.limit,
-        }
-    }
-}
-
-#[derive(AnchorSerialize, AnchorDeserialize, Debug, Copy, Clone)]
-pub struct PlaceTakeOrderArgs {
-    pub side: Side,
-    pub price_lots: i64,
-    pub max_base_lots: i64,
-    pub max_quote_lots_including_fees: i64,
-    pub order_type: PlaceOrderType,
-    pub limit: u8,
-}
-
-impl From<PlaceTakeOrderArgs> for openbook_v2::PlaceTakeOrderArgs {
-    fn from(args: PlaceTakeOrderArgs) -> Self {
-        Self {
-            side: args.side.into(),
-            price_lots: args.price_lots,
-            max_base_lots: args.max_base_lots,
-            max_quote_lots_including_fees: args.max_quote_lots_including_fees,
-            order_type: args.order_type.into(),
-            limit: args.limit,
-        }
-    }
-}
-
-#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
-#[repr(u8)]
-pub enum SelfTradeBehavior {
-    DecrementTake = 0,
-    CancelProvide = 1,
-    AbortTransaction = 2,
-}
-
-impl From<SelfTradeBehavior> for openbook_v2::state::SelfTradeBehavior {
-    fn from(behavior: SelfTradeBehavior) -> Self {
-        match behavior {
-            SelfTradeBehavior::DecrementTake => Self::DecrementTake,
-            SelfTradeBehavior::CancelProvide => Self::CancelProvide,
-            SelfTradeBehavior::AbortTransaction => Self::AbortTransaction,
-        }
-    }
-}
+#[cfg(test)]
+mod test_utils;
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
-#[repr(u8)]
-pub enum PlaceOrderType {
-    Limit = 0,
-    ImmediateOrCancel = 1,
-    PostOnly = 2,
-    Market = 3,
-    PostOnlySlide = 4,
-}
-
-impl From<PlaceOrderType> for openbook_v2::state::PlaceOrderType {
-    fn from(order_type: PlaceOrderType) -> Self {
-        match order_type {
-            PlaceOrderType::Limit => Self::Limit,
-            PlaceOrderType::ImmediateOrCancel => Self::ImmediateOrCancel,
-            PlaceOrderType::PostOnly => Self::PostOnly,
-            PlaceOrderType::Market => Self::Market,
-            PlaceOrderType::PostOnlySlide => Self::PostOnlySlide,
-        }
-    }
-}
+pub mod decision;
+pub mod error;
+pub mod events;
+pub mod instructions;
+pub mod oracle;
+pub mod twap_market;
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
-#[repr(u8)]
-pub enum Side {
-    Bid = 0,
-    Ask = 1,
-}
+pub use decision::*;
+pub use error::*;
+pub use events::*;
+pub use instructions::*;
+pub use oracle::*;
+pub use twap_market::*;
 
-impl From<Side> for openbook_v2::state::Side {
-    fn from(side: Side) -> Self {
-        match side {
-            Side::Bid => Self::Bid,
-            Side::Ask => Self::Ask,
-        }
-    }
-}
+declare_id!("twAP5sArq2vDS1mZCT7f4qRLwzTfHvf5Ay5R5Q5df1m");
 
 #[program]
 pub mod openbook_twap {
@@ -435,8 +46,7 @@ pub mod openbook_twap {
     /// necessary for anti-manipulation
     pub fn create_twap_market(
         ctx: Context<CreateTWAPMarket>,
-        expected_value: u64,
-        max_observation_change_per_update_lots: u64,
+        args: CreateTWAPMarketArgs,
     ) -> Result<()> {
         let market = ctx.accounts.market.load()?;
         let twap_market = &mut ctx.accounts.twap_market;
@@ -459,11 +69,159 @@ pub mod openbook_twap {
         require!(market.maker_fee == 0, OpenBookTWAPError::InvalidMakerFee);
         require!(market.taker_fee == 0, OpenBookTWAPError::InvalidTakerFee);
 
-        twap_market.pda_bump = *ctx.bumps.get("twap_market").unwrap();
-        twap_market.market = ctx.accounts.market.key();
-        twap_market.twap_oracle =
-            TWAPOracle::new(expected_value, max_observation_change_per_update_lots);
-        twap_market.close_market_rent_receiver = ctx.accounts.payer.key();
+        twap_market.init(
+            ctx.accounts.market.key(),
+            *ctx.bumps.get("twap_market").unwrap(),
+            ctx.accounts.payer.key(),
+            &args,
+        )
+    }
+
+    pub fn create_market_with_twap(
+        ctx: Context<CreateMarketWithTWAP>,
+        name: String,
+        quote_lot_size: i64,
+        base_lot_size: i64,
+        time_expiry: i64,
+        args: CreateTWAPMarketArgs,
+    ) -> Result<()> {
+        let twap_market = ctx.accounts.twap_market.to_account_info();
+
+        openbook_v2::cpi::create_market(
+            CpiContext::new(
+                ctx.accounts.openbook_program.to_account_info(),
+                openbook_v2::cpi::accounts::CreateMarket {
+                    market: ctx.accounts.market.to_account_info(),
+                    market_authority: ctx.accounts.market_authority.to_account_info(),
+                    bids: ctx.accounts.bids.to_account_info(),
+                    asks: ctx.accounts.asks.to_account_info(),
+                    event_heap: ctx.accounts.event_heap.to_account_info(),
+                    payer: ctx.accounts.payer.to_account_info(),
+                    market_base_vault: ctx.accounts.market_base_vault.to_account_info(),
+                    market_quote_vault: ctx.accounts.market_quote_vault.to_account_info(),
+                    base_mint: ctx.accounts.base_mint.to_account_info(),
+                    quote_mint: ctx.accounts.quote_mint.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                    associated_token_program: ctx
+                        .accounts
+                        .associated_token_program
+                        .to_account_info(),
+                    oracle_a: None,
+                    oracle_b: None,
+                    collect_fee_admin: ctx.accounts.payer.to_account_info(),
+                    open_orders_admin: Some(twap_market.clone()),
+                    consume_events_admin: None,
+                    close_market_admin: Some(twap_market),
+                    event_authority: ctx.accounts.event_authority.to_account_info(),
+                    program: ctx.accounts.openbook_program.to_account_info(),
+                },
+            ),
+            name,
+            // unused, since the market has no oracles
+            openbook_v2::state::OracleConfigParams {
+                conf_filter: 0.1,
+                max_staleness_slots: None,
+            },
+            quote_lot_size,
+            base_lot_size,
+            0,
+            0,
+            time_expiry,
+        )?;
+
+        ctx.accounts.twap_market.init(
+            ctx.accounts.market.key(),
+            *ctx.bumps.get("twap_market").unwrap(),
+            ctx.accounts.payer.key(),
+            &args,
+        )
+    }
+
+    pub fn create_open_orders_account(
+        ctx: Context<CreateOpenOrdersAccount>,
+        name: String,
+    ) -> Result<()> {
+        // openbook numbers each owner's open orders accounts from 1
+        let account_num = if ctx.accounts.open_orders_indexer.data_is_empty() {
+            1
+        } else {
+            let indexer = OpenOrdersIndexer::try_deserialize(
+                &mut &ctx.accounts.open_orders_indexer.try_borrow_data()?[..],
+            )?;
+
+            indexer.created_counter + 1
+        };
+        let (open_orders_account, _) = Pubkey::find_program_address(
+            &[
+                b"OpenOrders",
+                ctx.accounts.owner.key().as_ref(),
+                &account_num.to_le_bytes(),
+            ],
+            &openbook_v2::ID,
+        );
+        require_keys_eq!(
+            ctx.accounts.open_orders_account.key(),
+            open_orders_account,
+            OpenBookTWAPError::InvalidOpenOrdersAccount
+        );
+
+        if ctx.accounts.open_orders_indexer.data_is_empty() {
+            openbook_v2::cpi::create_open_orders_indexer(CpiContext::new(
+                ctx.accounts.openbook_program.to_account_info(),
+                openbook_v2::cpi::accounts::CreateOpenOrdersIndexer {
+                    payer: ctx.accounts.payer.to_account_info(),
+                    owner: ctx.accounts.owner.to_account_info(),
+                    open_orders_indexer: ctx.accounts.open_orders_indexer.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                },
+            ))?;
+        }
+
+        openbook_v2::cpi::create_open_orders_account(
+            CpiContext::new(
+                ctx.accounts.openbook_program.to_account_info(),
+                openbook_v2::cpi::accounts::CreateOpenOrdersAccount {
+                    payer: ctx.accounts.payer.to_account_info(),
+                    owner: ctx.accounts.owner.to_account_info(),
+                    delegate_account: None,
+                    open_orders_indexer: ctx.accounts.open_orders_indexer.to_account_info(),
+                    open_orders_account: ctx.accounts.open_orders_account.to_account_info(),
+                    market: ctx.accounts.market.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                },
+            ),
+            name,
+        )?;
+
+        emit!(TWAPMarketAction::new(
+            ctx.accounts.twap_market.key(),
+            Some(ctx.accounts.owner.key()),
+            Some(ctx.accounts.open_orders_account.key()),
+            MarketAction::CreateOpenOrdersAccount
+        ));
+
+        Ok(())
+    }
+
+    pub fn close_open_orders_account(ctx: Context<CloseOpenOrdersAccount>) -> Result<()> {
+        openbook_v2::cpi::close_open_orders_account(CpiContext::new(
+            ctx.accounts.openbook_program.to_account_info(),
+            openbook_v2::cpi::accounts::CloseOpenOrdersAccount {
+                owner: ctx.accounts.owner.to_account_info(),
+                open_orders_indexer: ctx.accounts.open_orders_indexer.to_account_info(),
+                open_orders_account: ctx.accounts.open_orders_account.to_account_info(),
+                sol_destination: ctx.accounts.sol_destination.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+            },
+        ))?;
+
+        emit!(TWAPMarketAction::new(
+            ctx.accounts.twap_market.key(),
+            Some(ctx.accounts.owner.key()),
+            Some(ctx.accounts.open_orders_account.key()),
+            MarketAction::CloseOpenOrdersAccount
+        ));
 
         Ok(())
     }
@@ -472,12 +230,15 @@ pub mod openbook_twap {
         ctx: Context<PlaceOrder>,
         place_order_args: PlaceOrderArgs,
     ) -> Result<Option<u128>> {
+        let twap_market = ctx.accounts.twap_market.key();
         let oracle = &mut ctx.accounts.twap_market.twap_oracle;
 
+        oracle.require_trading_allowed(Clock::get()?.slot)?;
+
         let bids = ctx.accounts.bids.load()?;
         let asks = ctx.accounts.asks.load()?;
 
-        oracle.update_oracle(bids, asks);
+        oracle.update_oracle(twap_market, bids, asks);
 
         let market_key = ctx.accounts.market.key();
         let twap_market_seeds =
@@ -503,7 +264,20 @@ pub mod openbook_twap {
 
         let retval = openbook_v2::cpi::place_order(cpi_ctx, place_order_args.into())?;
 
-        Ok(retval.get())
+        let order_id = retval.get();
+
+        emit!(TWAPMarketAction {
+            order_ids: vec![order_id],
+            order: Some(place_order_args.into()),
+            ..TWAPMarketAction::new(
+                ctx.accounts.twap_market.key(),
+                Some(ctx.accounts.signer.key()),
+                Some(ctx.accounts.open_orders_account.key()),
+                MarketAction::PlaceOrder
+            )
+        });
+
+        Ok(order_id)
     }
 
     pub fn edit_order<'info>(
@@ -512,12 +286,15 @@ pub mod openbook_twap {
         expected_cancel_size: i64,
         place_order: PlaceOrderArgs,
     ) -> Result<Option<u128>> {
+        let twap_market = ctx.accounts.twap_market.key();
         let oracle = &mut ctx.accounts.twap_market.twap_oracle;
 
+        oracle.require_trading_allowed(Clock::get()?.slot)?;
+
         let bids = ctx.accounts.bids.load()?;
         let asks = ctx.accounts.asks.load()?;
 
-        oracle.update_oracle(bids, asks);
+        oracle.update_oracle(twap_market, bids, asks);
 
         let market_key = ctx.accounts.market.key();
 
@@ -549,7 +326,20 @@ pub mod openbook_twap {
             place_order.into(),
         )?;
 
-        Ok(retval.get())
+        let order_id = retval.get();
+
+        emit!(TWAPMarketAction {
+            order_ids: vec![order_id],
+            order: Some(place_order.into()),
+            ..TWAPMarketAction::new(
+                ctx.accounts.twap_market.key(),
+                Some(ctx.accounts.signer.key()),
+                Some(ctx.accounts.open_orders_account.key()),
+                MarketAction::EditOrder
+            )
+        });
+
+        Ok(order_id)
     }
 
     // Context<CancelOrder> endpoints
@@ -560,12 +350,13 @@ pub mod openbook_twap {
         ctx: Context<CancelOrder>,
         client_order_id: u64,
     ) -> Result<i64> {
+        let twap_market = ctx.accounts.twap_market.key();
         let oracle = &mut ctx.accounts.twap_market.twap_oracle;
 
         let bids = ctx.accounts.bids.load()?;
         let asks = ctx.accounts.asks.load()?;
 
-        oracle.update_oracle(bids, asks);
+        oracle.update_oracle(twap_market, bids, asks);
 
         let market_key = ctx.accounts.market.key();
 
@@ -588,7 +379,19 @@ pub mod openbook_twap {
             client_order_id,
         )?;
 
-        Ok(retval.get())
+        let cancelled_size = retval.get();
+
+        emit!(TWAPMarketAction {
+            cancelled_size: Some(cancelled_size),
+            ..TWAPMarketAction::new(
+                ctx.accounts.twap_market.key(),
+                Some(ctx.accounts.signer.key()),
+                Some(ctx.accounts.open_orders_account.key()),
+                MarketAction::CancelOrderByClientId
+            )
+        });
+
+        Ok(cancelled_size)
     }
 
     pub fn cancel_all_orders(
@@ -596,12 +399,13 @@ pub mod openbook_twap {
         side_option: Option<Side>,
         limit: u8,
     ) -> Result<()> {
+        let twap_market = ctx.accounts.twap_market.key();
         let oracle = &mut ctx.accounts.twap_market.twap_oracle;
 
         let bids = ctx.accounts.bids.load()?;
         let asks = ctx.accounts.asks.load()?;
 
-        oracle.update_oracle(bids, asks);
+        oracle.update_oracle(twap_market, bids, asks);
 
         let market_key = ctx.accounts.market.key();
 
@@ -630,6 +434,13 @@ pub mod openbook_twap {
             limit,
         )?;
 
+        emit!(TWAPMarketAction::new(
+            ctx.accounts.twap_market.key(),
+            Some(ctx.accounts.signer.key()),
+            Some(ctx.accounts.open_orders_account.key()),
+            MarketAction::CancelAllOrders
+        ));
+
         Ok(())
     }
 
@@ -655,6 +466,13 @@ pub mod openbook_twap {
             limit,
         )?;
 
+        emit!(TWAPMarketAction::new(
+            ctx.accounts.twap_market.key(),
+            None,
+            Some(ctx.accounts.open_orders_account.key()),
+            MarketAction::PruneOrders
+        ));
+
         Ok(())
     }
 
@@ -687,10 +505,41 @@ pub mod openbook_twap {
             ),
         )?;
 
+        emit!(TWAPMarketAction::new(
+            ctx.accounts.twap_market.key(),
+            None,
+            Some(ctx.accounts.open_orders_account.key()),
+            MarketAction::SettleFundsExpired
+        ));
+
         Ok(())
     }
 
-    pub fn close_market<'info>(ctx: Context<CloseMarket>) -> Result<()> {
+    pub fn close_market(ctx: Context<CloseMarket>) -> Result<()> {
+        // a TWAP with an end can only be closed once it's recorded, so that
+        // the account can't disappear before its final TWAP is read, nor
+        // before the decisions that depend on it are resolved
+        let oracle = &ctx.accounts.twap_market.twap_oracle;
+        require!(
+            oracle.end_slot.is_none() || oracle.final_twap.is_some(),
+            OpenBookTWAPError::TWAPNotFinalized
+        );
+        require!(
+            ctx.accounts.twap_market.unresolved_decisions == 0,
+            OpenBookTWAPError::UnresolvedDecisions
+        );
+
+        if let Some(close_authority) = ctx.accounts.twap_market.close_authority {
+            require!(
+                ctx.accounts
+                    .close_authority
+                    .as_ref()
+                    .map(|signer| signer.key())
+                    == Some(close_authority),
+                OpenBookTWAPError::InvalidCloseAuthority
+            );
+        }
+
         let market_key = ctx.accounts.market.key();
 
         let seeds =
@@ -712,6 +561,86 @@ pub mod openbook_twap {
                 signer_seeds,
             )
         )?;
+
+        emit!(TWAPMarketAction::new(
+            ctx.accounts.twap_market.key(),
+            ctx.accounts
+                .close_authority
+                .as_ref()
+                .map(|signer| signer.key()),
+            None,
+            MarketAction::CloseMarket
+        ));
+
+        let oracle = &ctx.accounts.twap_market.twap_oracle;
+
+        emit!(TWAPMarketClosed {
+            twap_market: ctx.accounts.twap_market.key(),
+            market: market_key,
+            initial_slot: oracle.initial_slot,
+            last_updated_slot: oracle.last_updated_slot,
+            last_observation: oracle.last_observation,
+            observation_aggregator: oracle.observation_aggregator,
+            twap: oracle
+                .twap(TWAPWeighting::Slot, None, oracle.last_updated_slot)
+                .ok(),
+            final_twap: oracle.final_twap,
+        });
+
+        Ok(())
+    }
+
+    pub fn migrate_twap_market(ctx: Context<MigrateTWAPMarket>) -> Result<()> {
+        let twap_market_info = ctx.accounts.twap_market.to_account_info();
+
+        let clock = Clock::get()?;
+
+        let from_version = TWAPMarket::layout_version(&twap_market_info.try_borrow_data()?)?;
+        // v0.2's is the only layout older than the current one
+        require!(
+            from_version == 0,
+            OpenBookTWAPError::TWAPMarketAlreadyMigrated
+        );
+
+        let rent = Rent::get()?
+            .minimum_balance(TWAP_MARKET_SPACE)
+            .saturating_sub(twap_market_info.lamports());
+
+        if rent > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: twap_market_info.clone(),
+                    },
+                ),
+                rent,
+            )?;
+        }
+
+        twap_market_info.realloc(TWAP_MARKET_SPACE, true)?;
+
+        TWAPMarket::migrate_v0_2(
+            &mut twap_market_info.try_borrow_mut_data()?,
+            clock.unix_timestamp,
+        )?;
+
+        emit!(TWAPMarketMigrated {
+            twap_market: twap_market_info.key(),
+            from_version,
+            to_version: TWAP_MARKET_VERSION,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_close_authority(
+        ctx: Context<SetCloseAuthority>,
+        new_close_authority: Option<Pubkey>,
+    ) -> Result<()> {
+        ctx.accounts.twap_market.close_authority = new_close_authority;
+
         Ok(())
     }
 
@@ -723,12 +652,15 @@ pub mod openbook_twap {
         ctx: Context<'_, '_, '_, 'info, PlaceTakeOrder<'info>>,
         args: PlaceTakeOrderArgs,
     ) -> Result<()> {
+        let twap_market = ctx.accounts.twap_market.key();
         let oracle = &mut ctx.accounts.twap_market.twap_oracle;
 
+        oracle.require_trading_allowed(Clock::get()?.slot)?;
+
         let bids = ctx.accounts.bids.load()?;
         let asks = ctx.accounts.asks.load()?;
 
-        oracle.update_oracle(bids, asks);
+        oracle.update_oracle(twap_market, bids, asks);
 
         let market_key = ctx.accounts.market.key();
 
@@ -762,6 +694,17 @@ pub mod openbook_twap {
             ),
             args.into(),
         )?;
+
+        emit!(TWAPMarketAction {
+            order: Some(args.into()),
+            ..TWAPMarketAction::new(
+                ctx.accounts.twap_market.key(),
+                Some(ctx.accounts.signer.key()),
+                None,
+                MarketAction::PlaceTakeOrder
+            )
+        });
+
         Ok(())
     }
 
@@ -770,12 +713,15 @@ pub mod openbook_twap {
         cancel_client_orders_ids: Vec<u64>,
         place_orders: Vec<PlaceOrderArgs>,
     ) -> Result<Vec<Option<u128>>> {
+        let twap_market = ctx.accounts.twap_market.key();
         let oracle = &mut ctx.accounts.twap_market.twap_oracle;
 
+        oracle.require_trading_allowed(Clock::get()?.slot)?;
+
         let bids = ctx.accounts.bids.load()?;
         let asks = ctx.accounts.asks.load()?;
 
-        oracle.update_oracle(bids, asks);
+        oracle.update_oracle(twap_market, bids, asks);
 
         let market_key = ctx.accounts.market.key();
 
@@ -811,7 +757,302 @@ pub mod openbook_twap {
             cpi_place_orders,
         )?;
 
-        Ok(retval.get())
+        let order_ids = retval.get();
+
+        emit!(TWAPMarketAction {
+            order_ids: order_ids.clone(),
+            ..TWAPMarketAction::new(
+                ctx.accounts.twap_market.key(),
+                Some(ctx.accounts.signer.key()),
+                Some(ctx.accounts.open_orders_account.key()),
+                MarketAction::CancelAndPlaceOrders
+            )
+        });
+
+        Ok(order_ids)
+    }
+
+    // Context<Deposit> endpoints
+    // deposit
+    // refill
+
+    pub fn deposit(ctx: Context<Deposit>, base_amount: u64, quote_amount: u64) -> Result<()> {
+        update_oracle_if_book_passed(
+            &mut ctx.accounts.twap_market,
+            &ctx.accounts.bids,
+            &ctx.accounts.asks,
+        )?;
+
+        openbook_v2::cpi::deposit(
+            CpiContext::new(
+                ctx.accounts.openbook_program.to_account_info(),
+                openbook_v2::cpi::accounts::Deposit {
+                    owner: ctx.accounts.owner.to_account_info(),
+                    user_base_account: ctx.accounts.user_base_account.to_account_info(),
+                    user_quote_account: ctx.accounts.user_quote_account.to_account_info(),
+                    open_orders_account: ctx.accounts.open_orders_account.to_account_info(),
+                    market: ctx.accounts.market.to_account_info(),
+                    market_base_vault: ctx.accounts.market_base_vault.to_account_info(),
+                    market_quote_vault: ctx.accounts.market_quote_vault.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                },
+            ),
+            base_amount,
+            quote_amount,
+        )?;
+
+        emit!(TWAPMarketAction::new(
+            ctx.accounts.twap_market.key(),
+            Some(ctx.accounts.owner.key()),
+            Some(ctx.accounts.open_orders_account.key()),
+            MarketAction::Deposit
+        ));
+
+        Ok(())
+    }
+
+    pub fn refill(ctx: Context<Deposit>, base_amount: u64, quote_amount: u64) -> Result<()> {
+        update_oracle_if_book_passed(
+            &mut ctx.accounts.twap_market,
+            &ctx.accounts.bids,
+            &ctx.accounts.asks,
+        )?;
+
+        openbook_v2::cpi::refill(
+            CpiContext::new(
+                ctx.accounts.openbook_program.to_account_info(),
+                openbook_v2::cpi::accounts::Deposit {
+                    owner: ctx.accounts.owner.to_account_info(),
+                    user_base_account: ctx.accounts.user_base_account.to_account_info(),
+                    user_quote_account: ctx.accounts.user_quote_account.to_account_info(),
+                    open_orders_account: ctx.accounts.open_orders_account.to_account_info(),
+                    market: ctx.accounts.market.to_account_info(),
+                    market_base_vault: ctx.accounts.market_base_vault.to_account_info(),
+                    market_quote_vault: ctx.accounts.market_quote_vault.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                },
+            ),
+            base_amount,
+            quote_amount,
+        )?;
+
+        emit!(TWAPMarketAction::new(
+            ctx.accounts.twap_market.key(),
+            Some(ctx.accounts.owner.key()),
+            Some(ctx.accounts.open_orders_account.key()),
+            MarketAction::Refill
+        ));
+
+        Ok(())
+    }
+
+    pub fn settle_funds(ctx: Context<SettleFunds>) -> Result<()> {
+        update_oracle_if_book_passed(
+            &mut ctx.accounts.twap_market,
+            &ctx.accounts.bids,
+            &ctx.accounts.asks,
+        )?;
+
+        openbook_v2::cpi::settle_funds(CpiContext::new(
+            ctx.accounts.openbook_program.to_account_info(),
+            openbook_v2::cpi::accounts::SettleFunds {
+                owner: ctx.accounts.owner.to_account_info(),
+                penalty_payer: ctx.accounts.owner.to_account_info(),
+                open_orders_account: ctx.accounts.open_orders_account.to_account_info(),
+                market: ctx.accounts.market.to_account_info(),
+                market_authority: ctx.accounts.market_authority.to_account_info(),
+                market_base_vault: ctx.accounts.market_base_vault.to_account_info(),
+                market_quote_vault: ctx.accounts.market_quote_vault.to_account_info(),
+                user_base_account: ctx.accounts.user_base_account.to_account_info(),
+                user_quote_account: ctx.accounts.user_quote_account.to_account_info(),
+                referrer_account: None,
+                token_program: ctx.accounts.token_program.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+            },
+        ))?;
+
+        emit!(TWAPMarketAction::new(
+            ctx.accounts.twap_market.key(),
+            Some(ctx.accounts.owner.key()),
+            Some(ctx.accounts.open_orders_account.key()),
+            MarketAction::SettleFunds
+        ));
+
+        Ok(())
+    }
+
+    // Context<ConsumeEvents> endpoints
+    // consume_events
+    // consume_given_events
+
+    pub fn consume_events<'info>(
+        ctx: Context<'_, '_, '_, 'info, ConsumeEvents<'info>>,
+        limit: usize,
+    ) -> Result<()> {
+        let twap_market = ctx.accounts.twap_market.key();
+        let oracle = &mut ctx.accounts.twap_market.twap_oracle;
+
+        let bids = ctx.accounts.bids.load()?;
+        let asks = ctx.accounts.asks.load()?;
+
+        oracle.update_oracle(twap_market, bids, asks);
+
+        openbook_v2::cpi::consume_events(
+            CpiContext::new(
+                ctx.accounts.openbook_program.to_account_info(),
+                openbook_v2::cpi::accounts::ConsumeEvents {
+                    consume_events_admin: None,
+                    market: ctx.accounts.market.to_account_info(),
+                    event_heap: ctx.accounts.event_heap.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            limit,
+        )?;
+
+        emit!(TWAPMarketAction::new(
+            twap_market,
+            None,
+            None,
+            MarketAction::ConsumeEvents
+        ));
+
+        Ok(())
+    }
+
+    pub fn consume_given_events<'info>(
+        ctx: Context<'_, '_, '_, 'info, ConsumeEvents<'info>>,
+        slots: Vec<usize>,
+    ) -> Result<()> {
+        let twap_market = ctx.accounts.twap_market.key();
+        let oracle = &mut ctx.accounts.twap_market.twap_oracle;
+
+        let bids = ctx.accounts.bids.load()?;
+        let asks = ctx.accounts.asks.load()?;
+
+        oracle.update_oracle(twap_market, bids, asks);
+
+        openbook_v2::cpi::consume_given_events(
+            CpiContext::new(
+                ctx.accounts.openbook_program.to_account_info(),
+                openbook_v2::cpi::accounts::ConsumeEvents {
+                    consume_events_admin: None,
+                    market: ctx.accounts.market.to_account_info(),
+                    event_heap: ctx.accounts.event_heap.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            slots,
+        )?;
+
+        emit!(TWAPMarketAction::new(
+            twap_market,
+            None,
+            None,
+            MarketAction::ConsumeEvents
+        ));
+
+        Ok(())
+    }
+
+    pub fn create_decision_pair(
+        ctx: Context<CreateDecisionPair>,
+        start_slot: u64,
+        end_slot: u64,
+        pass_threshold_bps: u64,
+    ) -> Result<()> {
+        DecisionPair::check_window(
+            &ctx.accounts.pass_twap_market.twap_oracle,
+            start_slot,
+            end_slot,
+        )?;
+        DecisionPair::check_window(
+            &ctx.accounts.fail_twap_market.twap_oracle,
+            start_slot,
+            end_slot,
+        )?;
+        require!(
+            pass_threshold_bps <= 10_000,
+            OpenBookTWAPError::InvalidPassThreshold
+        );
+
+        ctx.accounts.pass_twap_market.unresolved_decisions += 1;
+        ctx.accounts.fail_twap_market.unresolved_decisions += 1;
+
+        let decision_pair = &mut ctx.accounts.decision_pair;
+
+        decision_pair.authority = ctx.accounts.authority.key();
+        decision_pair.pass_twap_market = ctx.accounts.pass_twap_market.key();
+        decision_pair.fail_twap_market = ctx.accounts.fail_twap_market.key();
+        decision_pair.start_slot = start_slot;
+        decision_pair.end_slot = end_slot;
+        decision_pair.pass_threshold_bps = pass_threshold_bps;
+        decision_pair.outcome = DecisionOutcome::Pending;
+        decision_pair.pda_bump = *ctx.bumps.get("decision_pair").unwrap();
+
+        Ok(())
+    }
+
+    pub fn resolve_decision(ctx: Context<ResolveDecision>) -> Result<()> {
+        let clock = Clock::get()?;
+
+        let decision_pair = &mut ctx.accounts.decision_pair;
+
+        let outcome = decision_pair.resolve(
+            &ctx.accounts.pass_twap_market.twap_oracle,
+            &ctx.accounts.fail_twap_market.twap_oracle,
+            clock.slot,
+        )?;
+
+        ctx.accounts.pass_twap_market.unresolved_decisions -= 1;
+        ctx.accounts.fail_twap_market.unresolved_decisions -= 1;
+
+        emit!(DecisionResolved {
+            decision_pair: decision_pair.key(),
+            pass_twap: decision_pair.pass_twap.unwrap(),
+            fail_twap: decision_pair.fail_twap.unwrap(),
+            outcome,
+        });
+
+        Ok(())
+    }
+
+    pub fn close_decision_pair(ctx: Context<CloseDecisionPair>) -> Result<()> {
+        require!(
+            ctx.accounts.decision_pair.outcome != DecisionOutcome::Pending,
+            OpenBookTWAPError::DecisionNotResolved
+        );
+
+        Ok(())
+    }
+
+    pub fn crank_twap(ctx: Context<CrankTWAP>) -> Result<()> {
+        let twap_market = ctx.accounts.twap_market.key();
+        let oracle = &mut ctx.accounts.twap_market.twap_oracle;
+
+        let bids = ctx.accounts.bids.load()?;
+        let asks = ctx.accounts.asks.load()?;
+
+        oracle.update_oracle(twap_market, bids, asks);
+
+        Ok(())
+    }
+
+    pub fn finalize_twap(ctx: Context<FinalizeTWAP>) -> Result<()> {
+        let twap_market = ctx.accounts.twap_market.key();
+        let oracle = &mut ctx.accounts.twap_market.twap_oracle;
+
+        let clock = Clock::get()?;
+
+        let final_twap = oracle.finalize(clock.slot, clock.unix_timestamp)?;
+
+        emit!(TWAPFinalized {
+            twap_market,
+            end_slot: oracle.end_slot.unwrap(),
+            final_twap,
+        });
+
+        Ok(())
     }
 
     pub fn get_best_bid_and_ask(ctx: Context<GetBestBidAndAsk>) -> Result<Vec<u64>> {
@@ -828,28 +1069,38 @@ pub mod openbook_twap {
 
         Ok(vec![best_bid as u64, best_ask as u64])
     }
-}
 
-#[error_code]
-pub enum OpenBookTWAPError {
-    #[msg(
-        "The `open_orders_admin` of the underlying market must be equal to the `TWAPMarket` PDA"
-    )]
-    InvalidOpenOrdersAdmin,
-    #[msg(
-        "The `close_market_admin` of the underlying market must be equal to the `TWAPMarket` PDA"
-    )]
-    InvalidCloseMarketAdmin,
-    #[msg(
-        "Oracle-pegged trades mess up the TWAP so oracles and oracle-pegged trades aren't allowed"
-    )]
-    NoOracles,
-    #[msg("Maker fee must be zero")]
-    InvalidMakerFee,
-    #[msg("Taker fee must be zero")]
-    InvalidTakerFee,
-    #[msg("Seq num must be zero")]
-    InvalidSeqNum,
-    #[msg("Consume events admin must be None")]
-    InvalidConsumeEventsAdmin,
+    pub fn get_twap(
+        ctx: Context<GetTWAP>,
+        weighting: TWAPWeighting,
+        start: Option<u64>,
+        end: Option<u64>,
+    ) -> Result<u64> {
+        let oracle = &ctx.accounts.twap_market.twap_oracle;
+
+        let clock = Clock::get()?;
+
+        let now = match weighting {
+            TWAPWeighting::Slot => clock.slot,
+            TWAPWeighting::Time => clock.unix_timestamp.max(0) as u64,
+        };
+
+        let end = end.unwrap_or(now);
+        require!(end <= now, OpenBookTWAPError::TWAPWindowInFuture);
+
+        // nothing is aggregated past the end, so don't extrapolate past it
+        let end = match weighting {
+            TWAPWeighting::Slot => oracle.end_slot.map_or(end, |end_slot| end.min(end_slot)),
+            TWAPWeighting::Time if oracle.has_ended(clock.slot) => {
+                let end_timestamp = oracle
+                    .end_timestamp
+                    .unwrap_or(oracle.last_updated_timestamp);
+
+                end.min(end_timestamp.max(0) as u64)
+            }
+            TWAPWeighting::Time => end,
+        };
+
+        oracle.twap(weighting, start, end)
+    }
 }
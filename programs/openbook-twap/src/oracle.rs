@@ -0,0 +1,1284 @@
+use anchor_lang::prelude::*;
+use num::integer::Average;
+use openbook_v2::state::BookSide;
+use std::cell::Ref;
+
+use crate::*;
+
+/// The number of checkpoints kept in each `TWAPOracle`'s ring buffer
+pub const MAX_CHECKPOINTS: usize = 32;
+
+/// The smallest `checkpoint_interval_slots` a new TWAP market accepts, about a minute
+pub const MIN_CHECKPOINT_INTERVAL_SLOTS: u64 = 150;
+
+pub const LOG2_FRACTIONAL_BITS: u32 = 32;
+
+/// A fixed-point log2 with `LOG2_FRACTIONAL_BITS` fractional bits. 0 is treated as 1.
+pub fn log2_fixed(x: u64) -> u64 {
+    let x = std::cmp::max(x, 1);
+
+    let integer_part = 63 - x.leading_zeros();
+
+    // normalize x into [1, 2) as a fixed-point number with 63 fractional
+    // bits, so that squaring it always fits in a u128
+    let one = 1u128 << 63;
+    let mut y = (x as u128) << (63 - integer_part);
+
+    let mut fractional_part = 0u64;
+    for bit in (0..LOG2_FRACTIONAL_BITS).rev() {
+        y = (y * y) >> 63;
+
+        if y >= 2 * one {
+            y >>= 1;
+            fractional_part |= 1 << bit;
+        }
+    }
+
+    ((integer_part as u64) << LOG2_FRACTIONAL_BITS) | fractional_part
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct TWAPOracle {
+    pub expected_value: u64,
+    pub initial_slot: u64,
+    /// Slots after creation during which observations aren't aggregated
+    pub start_delay_slots: u64,
+    pub end_slot: Option<u64>,
+    pub post_end_trading: PostEndTrading,
+    pub final_twap: Option<u64>,
+    pub last_updated_slot: u64,
+    pub last_observed_slot: u64,
+    pub last_observation: u64,
+    pub observation_aggregator: u128,
+    pub initial_timestamp: i64,
+    pub last_updated_timestamp: i64,
+    pub time_weighted_aggregator: u128,
+    /// Also aggregate `log2_fixed` of each observation, for a geometric-mean TWAP
+    pub accumulate_log_observations: bool,
+    pub log_observation_aggregator: u128,
+    pub max_observation_change_per_update_lots: u64,
+    pub max_observation_change_per_update_bps: u64,
+    pub observation_change_mode: ObservationChangeMode,
+    /// When non-zero, the max change is scaled by the slots elapsed, up to this many
+    pub max_observation_change_scaling_slots: u64,
+    pub max_spread_bps: u64,
+    pub wide_spread_behavior: WideSpreadBehavior,
+    pub checkpoint_interval_slots: u64,
+    pub num_checkpoints: u64,
+    pub checkpoints: [TWAPCheckpoint; MAX_CHECKPOINTS],
+    pub end_timestamp: Option<i64>,
+    pub reserved: [u8; 55],
+}
+
+impl Default for TWAPOracle {
+    fn default() -> Self {
+        Self {
+            expected_value: 0,
+            initial_slot: 0,
+            start_delay_slots: 0,
+            end_slot: None,
+            post_end_trading: PostEndTrading::Continue,
+            final_twap: None,
+            last_updated_slot: 0,
+            last_observed_slot: 0,
+            last_observation: 0,
+            observation_aggregator: 0,
+            initial_timestamp: 0,
+            last_updated_timestamp: 0,
+            time_weighted_aggregator: 0,
+            accumulate_log_observations: false,
+            log_observation_aggregator: 0,
+            max_observation_change_per_update_lots: 0,
+            max_observation_change_per_update_bps: 0,
+            observation_change_mode: ObservationChangeMode::Lots,
+            max_observation_change_scaling_slots: 0,
+            max_spread_bps: 0,
+            wide_spread_behavior: WideSpreadBehavior::Skip,
+            checkpoint_interval_slots: 0,
+            num_checkpoints: 0,
+            checkpoints: [TWAPCheckpoint::default(); MAX_CHECKPOINTS],
+            end_timestamp: None,
+            reserved: [0; 55],
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+#[repr(u8)]
+pub enum ObservationChangeMode {
+    Lots = 0,
+    BasisPoints = 1,
+}
+
+/// What to do when the spread is wider than `max_spread_bps`
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+#[repr(u8)]
+pub enum WideSpreadBehavior {
+    Skip = 0,
+    ClampTowardLastObservation = 1,
+    DampenTowardMidpoint = 2,
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+#[repr(u8)]
+pub enum PostEndTrading {
+    Continue = 0,
+    Reject = 1,
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+#[repr(u8)]
+pub enum TWAPWeighting {
+    Slot = 0,
+    Time = 1,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct TWAPCheckpoint {
+    pub slot: u64,
+    pub unix_timestamp: i64,
+    pub observation_aggregator: u128,
+    pub time_weighted_aggregator: u128,
+    pub log_observation_aggregator: u128,
+    pub last_observation: u64,
+}
+
+impl TWAPCheckpoint {
+    pub const SIZE: usize = 8 + 8 + 16 * 3 + 8;
+
+    fn position(&self, weighting: TWAPWeighting) -> (u64, u128) {
+        match weighting {
+            TWAPWeighting::Slot => (self.slot, self.observation_aggregator),
+            TWAPWeighting::Time => (
+                self.unix_timestamp.max(0) as u64,
+                self.time_weighted_aggregator,
+            ),
+        }
+    }
+}
+
+impl TWAPOracle {
+    pub const SIZE: usize = 8 // expected_value
+        + 8 // initial_slot
+        + 8 // start_delay_slots
+        + (1 + 8) // end_slot
+        + 1 // post_end_trading
+        + (1 + 8) // final_twap
+        + 8 // last_updated_slot
+        + 8 // last_observed_slot
+        + 8 // last_observation
+        + 16 // observation_aggregator
+        + 8 // initial_timestamp
+        + 8 // last_updated_timestamp
+        + 16 // time_weighted_aggregator
+        + 1 // accumulate_log_observations
+        + 16 // log_observation_aggregator
+        + 8 // max_observation_change_per_update_lots
+        + 8 // max_observation_change_per_update_bps
+        + 1 // observation_change_mode
+        + 8 // max_observation_change_scaling_slots
+        + 8 // max_spread_bps
+        + 1 // wide_spread_behavior
+        + 8 // checkpoint_interval_slots
+        + 8 // num_checkpoints
+        + TWAPCheckpoint::SIZE * MAX_CHECKPOINTS
+        + (1 + 8) // end_timestamp
+        + 55; // reserved
+
+    pub fn init(&mut self, args: &CreateTWAPMarketArgs) {
+        // Get the current slot at TWAPOracle initialization
+        // If we cannot get the clock the transaction should fail. Unwise to catch the error.
+        // Starting with a time of 0 (initial solana blockchain slot) messes up later logic in unpredictable ways
+
+        let clock = Clock::get().unwrap();
+
+        self.init_at(args, clock.slot, clock.unix_timestamp);
+    }
+
+    /// Writes each field in place, since the oracle is too large for the stack
+    pub fn init_at(&mut self, args: &CreateTWAPMarketArgs, slot: u64, unix_timestamp: i64) {
+        self.expected_value = args.expected_value;
+        self.initial_slot = slot;
+        self.start_delay_slots = args.start_delay_slots;
+        self.end_slot = args.end_slot;
+        self.post_end_trading = args.post_end_trading;
+        self.final_twap = None;
+        self.last_updated_slot = slot;
+        self.last_observed_slot = slot;
+        self.last_observation = args.expected_value;
+        self.initial_timestamp = unix_timestamp;
+        self.last_updated_timestamp = unix_timestamp;
+        self.accumulate_log_observations = args.accumulate_log_observations;
+        self.max_observation_change_per_update_lots = args.max_observation_change_per_update_lots;
+        self.max_observation_change_per_update_bps = args.max_observation_change_per_update_bps;
+        self.observation_change_mode = args.observation_change_mode;
+        self.max_observation_change_scaling_slots = args.max_observation_change_scaling_slots;
+        self.max_spread_bps = args.max_spread_bps;
+        self.wide_spread_behavior = args.wide_spread_behavior;
+        self.checkpoint_interval_slots = args.checkpoint_interval_slots;
+        self.num_checkpoints = 0;
+        self.checkpoints.fill(TWAPCheckpoint::default());
+        self.end_timestamp = None;
+        self.reserved.fill(0);
+
+        if args.start_delay_slots == 0 {
+            self.observation_aggregator = args.expected_value as u128;
+            self.time_weighted_aggregator = args.expected_value as u128;
+            self.log_observation_aggregator = if args.accumulate_log_observations {
+                log2_fixed(args.expected_value) as u128
+            } else {
+                0
+            };
+            self.push_checkpoint();
+        } else {
+            // nothing is aggregated until the warm-up is over
+            self.observation_aggregator = 0;
+            self.time_weighted_aggregator = 0;
+            self.log_observation_aggregator = 0;
+        }
+    }
+
+    pub fn new_at(args: &CreateTWAPMarketArgs, slot: u64, unix_timestamp: i64) -> Self {
+        let mut oracle = Self::default();
+        oracle.init_at(args, slot, unix_timestamp);
+
+        oracle
+    }
+
+    pub fn is_warmed_up(&self) -> bool {
+        self.num_checkpoints > 0
+    }
+
+    pub fn latest_checkpoint(&self) -> &TWAPCheckpoint {
+        let index = (self.num_checkpoints.saturating_sub(1) % MAX_CHECKPOINTS as u64) as usize;
+        &self.checkpoints[index]
+    }
+
+    pub fn checkpoints(&self) -> impl Iterator<Item = &TWAPCheckpoint> {
+        let len = std::cmp::min(self.num_checkpoints, MAX_CHECKPOINTS as u64);
+        let start = self.num_checkpoints - len;
+
+        (start..self.num_checkpoints)
+            .map(move |i| &self.checkpoints[(i % MAX_CHECKPOINTS as u64) as usize])
+    }
+
+    pub fn live_checkpoint(&self) -> TWAPCheckpoint {
+        TWAPCheckpoint {
+            slot: self.last_updated_slot,
+            unix_timestamp: self.last_updated_timestamp,
+            observation_aggregator: self.observation_aggregator,
+            time_weighted_aggregator: self.time_weighted_aggregator,
+            log_observation_aggregator: self.log_observation_aggregator,
+            last_observation: self.last_observation,
+        }
+    }
+
+    pub fn initial_position(&self, weighting: TWAPWeighting) -> u64 {
+        match weighting {
+            TWAPWeighting::Slot => self.initial_slot,
+            TWAPWeighting::Time => self.initial_timestamp.max(0) as u64,
+        }
+    }
+
+    pub fn aggregator_at(&self, weighting: TWAPWeighting, at: u64) -> Result<u128> {
+        require!(self.is_warmed_up(), OpenBookTWAPError::TWAPNotStarted);
+        require!(
+            at >= self.initial_position(weighting),
+            OpenBookTWAPError::TWAPWindowPrecedesMarket
+        );
+
+        // the live aggregator acts as the newest checkpoint, since it may
+        // have moved since the last checkpoint was written
+        let live = self.live_checkpoint();
+        let (live_at, live_aggregator) = live.position(weighting);
+
+        if at >= live_at {
+            return (self.last_observation as u128)
+                .checked_mul((at - live_at) as u128)
+                .and_then(|extrapolated| live_aggregator.checked_add(extrapolated))
+                .ok_or_else(|| error!(OpenBookTWAPError::MathOverflow));
+        }
+
+        let mut before: Option<(u64, u128)> = None;
+        for checkpoint in self.checkpoints().chain(std::iter::once(&live)) {
+            let (after_at, after_aggregator) = checkpoint.position(weighting);
+
+            if after_at > at {
+                let (before_at, before_aggregator) =
+                    before.ok_or_else(|| error!(OpenBookTWAPError::TWAPWindowOutOfRange))?;
+
+                let interpolated = (after_aggregator - before_aggregator)
+                    .checked_mul((at - before_at) as u128)
+                    .ok_or_else(|| error!(OpenBookTWAPError::MathOverflow))?
+                    / (after_at - before_at) as u128;
+
+                return Ok(before_aggregator + interpolated);
+            }
+
+            before = Some((after_at, after_aggregator));
+        }
+
+        unreachable!("the live checkpoint is always after `at`")
+    }
+
+    /// With a `start`, the TWAP over `(start, end]`. Without one, the TWAP since
+    /// the oracle started, which divides by `end - initial + 1`.
+    pub fn twap(&self, weighting: TWAPWeighting, start: Option<u64>, end: u64) -> Result<u64> {
+        if let Some(start) = start {
+            require!(end > start, OpenBookTWAPError::EmptyTWAPWindow);
+        }
+
+        let end_aggregator = self.aggregator_at(weighting, end)?;
+
+        let twap = match start {
+            // in u128 so that a window spanning every slot doesn't overflow
+            None => end_aggregator / ((end - self.initial_position(weighting)) as u128 + 1),
+            Some(start) => {
+                let start_aggregator = self.aggregator_at(weighting, start)?;
+
+                (end_aggregator - start_aggregator) / (end - start) as u128
+            }
+        };
+
+        u64::try_from(twap).map_err(|_| error!(OpenBookTWAPError::MathOverflow))
+    }
+
+    pub fn has_ended(&self, slot: u64) -> bool {
+        matches!(self.end_slot, Some(end_slot) if slot > end_slot)
+    }
+
+    pub fn require_trading_allowed(&self, slot: u64) -> Result<()> {
+        require!(
+            !self.has_ended(slot) || self.post_end_trading == PostEndTrading::Continue,
+            OpenBookTWAPError::TWAPMarketEnded
+        );
+
+        Ok(())
+    }
+
+    pub fn twap_at_end(&self) -> Result<u64> {
+        let end_slot = self
+            .end_slot
+            .ok_or_else(|| error!(OpenBookTWAPError::TWAPHasNoEndSlot))?;
+
+        if !self.is_warmed_up() {
+            return Ok(self.last_observation);
+        }
+
+        self.twap(TWAPWeighting::Slot, None, end_slot)
+    }
+
+    pub fn finalize(&mut self, current_slot: u64, unix_ts: i64) -> Result<u64> {
+        require!(self.end_slot.is_some(), OpenBookTWAPError::TWAPHasNoEndSlot);
+        require!(
+            self.has_ended(current_slot),
+            OpenBookTWAPError::TWAPNotEnded
+        );
+        require!(
+            self.final_twap.is_none(),
+            OpenBookTWAPError::TWAPAlreadyFinalized
+        );
+
+        let final_twap = self.twap_at_end()?;
+        self.final_twap = Some(final_twap);
+        self.record_end_timestamp(current_slot, unix_ts);
+
+        Ok(final_twap)
+    }
+
+    pub(crate) fn push_checkpoint(&mut self) {
+        let index = (self.num_checkpoints % MAX_CHECKPOINTS as u64) as usize;
+
+        self.checkpoints[index] = self.live_checkpoint();
+        self.num_checkpoints += 1;
+    }
+
+    pub fn max_observation_change(&self, slots_elapsed: u64) -> u64 {
+        let change = match self.observation_change_mode {
+            ObservationChangeMode::Lots => self.max_observation_change_per_update_lots as u128,
+            ObservationChangeMode::BasisPoints => {
+                let scaled_change = self.last_observation as u128
+                    * self.max_observation_change_per_update_bps as u128;
+
+                // we round up so that small observations can still move
+                let mut change = scaled_change / 10_000;
+                if change * 10_000 < scaled_change {
+                    change += 1;
+                }
+
+                change
+            }
+        };
+
+        let change = if self.max_observation_change_scaling_slots > 0 {
+            let scaling_slots =
+                std::cmp::min(slots_elapsed, self.max_observation_change_scaling_slots);
+
+            change.saturating_mul(std::cmp::max(scaling_slots, 1) as u128)
+        } else {
+            change
+        };
+
+        change.try_into().unwrap_or(u64::MAX)
+    }
+
+    pub fn clamp_observation(&self, spot_price: u64, slots_elapsed: u64) -> u64 {
+        let last_observation = self.last_observation;
+        let max_observation_change = self.max_observation_change(slots_elapsed);
+
+        if spot_price > last_observation {
+            let max_observation = last_observation.saturating_add(max_observation_change);
+
+            std::cmp::min(spot_price, max_observation)
+        } else {
+            let min_observation = last_observation.saturating_sub(max_observation_change);
+
+            std::cmp::max(spot_price, min_observation)
+        }
+    }
+
+    /// Rounded up, like v0.2's `best_ask > best_bid * 12 / 10` check
+    pub fn spread_bps(best_bid: i64, best_ask: i64) -> u64 {
+        if best_bid <= 0 {
+            return u64::MAX;
+        }
+
+        let scaled_spread = best_ask.saturating_sub(best_bid).max(0) as u128 * 10_000;
+        let best_bid = best_bid as u128;
+
+        let mut spread_bps = scaled_spread / best_bid;
+        if spread_bps * best_bid < scaled_spread {
+            spread_bps += 1;
+        }
+
+        spread_bps.try_into().unwrap_or(u64::MAX)
+    }
+
+    pub fn update_oracle(
+        &mut self,
+        twap_market: Pubkey,
+        bids: Ref<'_, BookSide>,
+        asks: Ref<'_, BookSide>,
+    ) {
+        let clock = Clock::get().unwrap();
+
+        let unix_ts: u64 = clock.unix_timestamp.try_into().unwrap();
+
+        let best_bid = bids.best_price(unix_ts, None);
+        let best_ask = asks.best_price(unix_ts, None);
+
+        match self.observe(clock.slot, clock.unix_timestamp, best_bid, best_ask) {
+            Ok(observation) => emit!(TWAPObservation {
+                twap_market,
+                slot: clock.slot,
+                unix_ts: clock.unix_timestamp,
+                best_bid: observation.best_bid,
+                best_ask: observation.best_ask,
+                spot_price: observation.spot_price,
+                clamped_observation: observation.clamped_observation,
+                weighted_observation: observation.weighted_observation,
+                aggregator: self.observation_aggregator,
+            }),
+            Err(reason) => emit!(TWAPObservationSkipped {
+                twap_market,
+                slot: clock.slot,
+                unix_ts: clock.unix_timestamp,
+                best_bid,
+                best_ask,
+                reason,
+            }),
+        }
+    }
+
+    pub fn spot_price(&self, best_bid: i64, best_ask: i64) -> Option<u64> {
+        if best_bid <= 0 || best_ask < best_bid {
+            return None;
+        }
+
+        let last_observation = self.last_observation;
+
+        // we use average_ceil because (best_bid + best_ask) / 2 can overflow
+        let spot_price = best_bid.average_ceil(&best_ask) as u64;
+
+        let spread_bps = Self::spread_bps(best_bid, best_ask);
+
+        if spread_bps <= self.max_spread_bps {
+            return Some(spot_price);
+        }
+
+        match self.wide_spread_behavior {
+            WideSpreadBehavior::Skip => None,
+            WideSpreadBehavior::ClampTowardLastObservation => {
+                Some(last_observation.clamp(best_bid as u64, best_ask as u64))
+            }
+            WideSpreadBehavior::DampenTowardMidpoint => {
+                // spread_bps > max_spread_bps, so this is a fraction below 1
+                let reduce = |distance: u64| {
+                    (distance as u128 * self.max_spread_bps as u128 / spread_bps as u128) as u64
+                };
+
+                Some(if spot_price > last_observation {
+                    last_observation + reduce(spot_price - last_observation)
+                } else {
+                    last_observation - reduce(last_observation - spot_price)
+                })
+            }
+        }
+    }
+
+    fn record_end_timestamp(&mut self, slot: u64, unix_ts: i64) {
+        if self.end_timestamp.is_none()
+            && matches!(self.end_slot, Some(end_slot) if slot >= end_slot)
+        {
+            // unix timestamps aren't guaranteed to be strictly increasing
+            self.end_timestamp = Some(std::cmp::max(unix_ts, self.last_updated_timestamp));
+        }
+    }
+
+    /// The oracle's logic, without reading the clock or the book
+    pub fn observe(
+        &mut self,
+        slot: u64,
+        unix_ts: i64,
+        best_bid: Option<i64>,
+        best_ask: Option<i64>,
+    ) -> std::result::Result<RecordedObservation, SkipReason> {
+        if self.last_observed_slot >= slot {
+            return Err(SkipReason::SameSlot);
+        }
+
+        self.record_end_timestamp(slot, unix_ts);
+
+        if self.has_ended(slot) {
+            return Err(SkipReason::Ended);
+        }
+
+        self.last_observed_slot = slot;
+
+        let (Some(best_bid), Some(best_ask)) = (best_bid, best_ask) else {
+            return Err(SkipReason::EmptySide);
+        };
+
+        let spot_price = self
+            .spot_price(best_bid, best_ask)
+            .ok_or(SkipReason::SpreadTooWide)?;
+
+        let observation = self.clamp_observation(spot_price, slot - self.last_updated_slot);
+
+        // unix timestamps aren't guaranteed to be strictly increasing
+        let unix_timestamp = std::cmp::max(unix_ts, self.last_updated_timestamp);
+
+        let weighted_observation = if !self.is_warmed_up() {
+            self.last_updated_slot = slot;
+            self.last_updated_timestamp = unix_timestamp;
+            self.last_observation = observation;
+
+            if slot < self.initial_slot.saturating_add(self.start_delay_slots) {
+                // still warming up, so nothing is aggregated
+                0
+            } else {
+                // start aggregating as if the oracle had just been created
+                // with this observation as its expected value
+                self.initial_slot = slot;
+                self.initial_timestamp = unix_timestamp;
+                self.observation_aggregator = observation as u128;
+                self.time_weighted_aggregator = observation as u128;
+                if self.accumulate_log_observations {
+                    self.log_observation_aggregator = log2_fixed(observation) as u128;
+                }
+                self.push_checkpoint();
+
+                observation as u128
+            }
+        } else {
+            let weighted_observation =
+                observation as u128 * (slot - self.last_updated_slot) as u128;
+
+            let time_weighted_observation =
+                observation as u128 * (unix_timestamp - self.last_updated_timestamp) as u128;
+
+            let log_weighted_observation = if self.accumulate_log_observations {
+                log2_fixed(observation) as u128 * (slot - self.last_updated_slot) as u128
+            } else {
+                0
+            };
+
+            self.last_updated_slot = slot;
+            self.last_updated_timestamp = unix_timestamp;
+            self.last_observation = observation;
+            self.observation_aggregator += weighted_observation;
+            self.time_weighted_aggregator += time_weighted_observation;
+            self.log_observation_aggregator += log_weighted_observation;
+
+            if slot
+                >= self
+                    .latest_checkpoint()
+                    .slot
+                    .saturating_add(self.checkpoint_interval_slots)
+            {
+                self.push_checkpoint();
+            }
+
+            weighted_observation
+        };
+
+        Ok(RecordedObservation {
+            best_bid,
+            best_ask,
+            spot_price,
+            clamped_observation: observation,
+            weighted_observation,
+        })
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub struct RecordedObservation {
+    pub best_bid: i64,
+    pub best_ask: i64,
+    pub spot_price: u64,
+    pub clamped_observation: u64,
+    pub weighted_observation: u128,
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+#[repr(u8)]
+pub enum SkipReason {
+    SameSlot = 0,
+    EmptySide = 1,
+    SpreadTooWide = 2,
+    Ended = 3,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    // a small xorshift PRNG, so that the property tests are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    fn assert_log2_close(x: u64) {
+        let expected = (x as f64).log2() * (1u64 << LOG2_FRACTIONAL_BITS) as f64;
+        let actual = log2_fixed(x) as f64;
+
+        // log2_fixed rounds down, and f64 loses a little precision on large
+        // inputs, so allow a couple of units in the last place either way
+        assert!(
+            (actual - expected).abs() <= 2.0,
+            "log2_fixed({}) = {}, expected {}",
+            x,
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_log2_fixed_exact_powers_of_two() {
+        for i in 0..64 {
+            assert_eq!(log2_fixed(1 << i), (i as u64) << LOG2_FRACTIONAL_BITS);
+        }
+
+        assert_eq!(log2_fixed(0), 0);
+    }
+
+    #[test]
+    fn test_log2_fixed_matches_f64() {
+        for x in [3, 5, 7, 10, 99, 1_000, 500_000, u32::MAX as u64, u64::MAX] {
+            assert_log2_close(x);
+        }
+
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..10_000 {
+            // spread inputs across all magnitudes rather than mostly huge ones
+            let x = rng.next() >> (rng.next() % 64);
+            assert_log2_close(std::cmp::max(x, 1));
+        }
+    }
+
+    #[test]
+    fn test_log2_fixed_is_monotonic() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..10_000 {
+            let a = rng.next() >> (rng.next() % 64);
+            let b = rng.next() >> (rng.next() % 64);
+
+            if a <= b {
+                assert!(log2_fixed(a) <= log2_fixed(b));
+            } else {
+                assert!(log2_fixed(a) >= log2_fixed(b));
+            }
+        }
+    }
+
+    #[test]
+    fn test_twap_by_slot_and_time() {
+        let mut oracle = new_oracle(100, ObservationChangeMode::Lots);
+        oracle.push_checkpoint();
+
+        record(&mut oracle, 10, 4, 200);
+        record(&mut oracle, 20, 12, 300);
+
+        // (100 + 200 * 10 + 300 * 10) / 21
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, None, 20).unwrap(), 242);
+        // (100 + 200 * 4 + 300 * 8) / 13
+        assert_eq!(oracle.twap(TWAPWeighting::Time, None, 12).unwrap(), 253);
+
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(0), 10).unwrap(), 200);
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(10), 20).unwrap(), 300);
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(0), 20).unwrap(), 250);
+        assert_eq!(oracle.twap(TWAPWeighting::Time, Some(0), 4).unwrap(), 200);
+        assert_eq!(oracle.twap(TWAPWeighting::Time, Some(4), 12).unwrap(), 300);
+        assert_eq!(oracle.twap(TWAPWeighting::Time, Some(0), 12).unwrap(), 266);
+    }
+
+    #[test]
+    fn test_twap_interpolates_and_extrapolates() {
+        let mut oracle = new_oracle(100, ObservationChangeMode::Lots);
+        oracle.push_checkpoint();
+
+        record(&mut oracle, 10, 10, 200);
+
+        // between checkpoints
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(5), 10).unwrap(), 200);
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(2), 7).unwrap(), 200);
+        // past the last update, we extrapolate the last observation
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(10), 30).unwrap(), 200);
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(0), 30).unwrap(), 200);
+        assert_eq!(oracle.twap(TWAPWeighting::Time, Some(0), 30).unwrap(), 200);
+    }
+
+    #[test]
+    fn test_twap_interpolation_with_sparse_checkpoints() {
+        let mut oracle = TWAPOracle::new_at(
+            &CreateTWAPMarketArgs {
+                checkpoint_interval_slots: 10,
+                ..new_args(100)
+            },
+            0,
+            0,
+        );
+
+        for slot in 1..=10 {
+            let price = if slot <= 5 { 100 } else { 200 };
+            oracle
+                .observe(slot, slot as i64, Some(price), Some(price))
+                .unwrap();
+        }
+        // only the initial checkpoint and the one at slot 10 were stored
+        assert_eq!(oracle.num_checkpoints, 2);
+
+        // the windows ending and starting at slot 10 are exact
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(0), 10).unwrap(), 150);
+        // but between checkpoints we interpolate as if the price was
+        // constant, when it was really 100 and then 200
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(0), 5).unwrap(), 150);
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, Some(5), 10).unwrap(), 150);
+
+        // (100 + 100 * 5 + 200 * 5) / 11, since the TWAP since the start
+        // includes the expected value
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, None, 10).unwrap(), 145);
+    }
+
+    #[test]
+    fn test_twap_window_errors() {
+        let mut oracle = new_oracle(100, ObservationChangeMode::Lots);
+        oracle.initial_slot = 5;
+        oracle.last_updated_slot = 5;
+
+        // nothing has been aggregated during a warm-up
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, None, 10).unwrap_err(),
+            error!(OpenBookTWAPError::TWAPNotStarted)
+        );
+
+        oracle.push_checkpoint();
+
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, Some(10), 10).unwrap_err(),
+            error!(OpenBookTWAPError::EmptyTWAPWindow)
+        );
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, Some(10), 9).unwrap_err(),
+            error!(OpenBookTWAPError::EmptyTWAPWindow)
+        );
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, Some(4), 10).unwrap_err(),
+            error!(OpenBookTWAPError::TWAPWindowPrecedesMarket)
+        );
+
+        // once the ring buffer wraps, the oldest history is gone
+        for i in 1..=MAX_CHECKPOINTS as u64 {
+            record(&mut oracle, 5 + i * 10, i as i64 * 10, 100);
+        }
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, Some(6), 100).unwrap_err(),
+            error!(OpenBookTWAPError::TWAPWindowOutOfRange)
+        );
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, Some(15), 100).unwrap(),
+            100
+        );
+    }
+
+    #[test]
+    fn test_finalize() {
+        let mut oracle = new_oracle(100, ObservationChangeMode::Lots);
+        oracle.push_checkpoint();
+
+        assert_eq!(
+            oracle.finalize(100, 100).unwrap_err(),
+            error!(OpenBookTWAPError::TWAPHasNoEndSlot)
+        );
+
+        oracle.end_slot = Some(20);
+        record(&mut oracle, 10, 10, 200);
+
+        assert_eq!(
+            oracle.finalize(20, 20).unwrap_err(),
+            error!(OpenBookTWAPError::TWAPNotEnded)
+        );
+
+        // (100 + 200 * 10 + 200 * 10) / 21, extrapolating up to the end slot
+        assert_eq!(oracle.finalize(21, 21).unwrap(), 195);
+        assert_eq!(oracle.final_twap, Some(195));
+        assert_eq!(oracle.end_timestamp, Some(21));
+
+        assert_eq!(
+            oracle.finalize(30, 30).unwrap_err(),
+            error!(OpenBookTWAPError::TWAPAlreadyFinalized)
+        );
+    }
+
+    #[test]
+    fn test_finalize_without_aggregating() {
+        let mut oracle = TWAPOracle::new_at(
+            &CreateTWAPMarketArgs {
+                start_delay_slots: 10,
+                end_slot: Some(10),
+                ..new_args(100)
+            },
+            0,
+            0,
+        );
+
+        // the warm-up moves the last observation, but the first observation
+        // that would start aggregating comes after the end
+        oracle.observe(5, 5, Some(150), Some(150)).unwrap();
+        assert_eq!(
+            oracle.observe(11, 11, Some(150), Some(150)),
+            Err(SkipReason::Ended)
+        );
+        assert!(!oracle.is_warmed_up());
+
+        assert_eq!(oracle.finalize(11, 11).unwrap(), 150);
+    }
+
+    #[test]
+    fn test_end_timestamp() {
+        let mut oracle = TWAPOracle::new_at(
+            &CreateTWAPMarketArgs {
+                end_slot: Some(10),
+                ..new_args(100)
+            },
+            0,
+            0,
+        );
+
+        oracle.observe(5, 50, Some(100), Some(100)).unwrap();
+        assert_eq!(oracle.end_timestamp, None);
+
+        // the first update at or after the end slot records it, even if it
+        // doesn't record an observation
+        assert_eq!(
+            oracle.observe(10, 100, None, Some(100)),
+            Err(SkipReason::EmptySide)
+        );
+        assert_eq!(oracle.end_timestamp, Some(100));
+
+        oracle.observe(11, 110, Some(100), Some(100)).unwrap_err();
+        oracle.finalize(12, 120).unwrap();
+        assert_eq!(oracle.end_timestamp, Some(100));
+    }
+
+    #[test]
+    fn test_trading_after_end() {
+        let mut oracle = new_oracle(100, ObservationChangeMode::Lots);
+        assert!(oracle.require_trading_allowed(u64::MAX).is_ok());
+
+        oracle.end_slot = Some(20);
+        assert!(!oracle.has_ended(20));
+        assert!(oracle.has_ended(21));
+        assert!(oracle.require_trading_allowed(21).is_ok());
+
+        oracle.post_end_trading = PostEndTrading::Reject;
+        assert!(oracle.require_trading_allowed(20).is_ok());
+        assert_eq!(
+            oracle.require_trading_allowed(21).unwrap_err(),
+            error!(OpenBookTWAPError::TWAPMarketEnded)
+        );
+    }
+
+    #[test]
+    fn test_clamp_observation_lots() {
+        let oracle = new_oracle(50_000, ObservationChangeMode::Lots);
+
+        assert_eq!(oracle.clamp_observation(50_500, 1), 50_500);
+        assert_eq!(oracle.clamp_observation(60_000, 1), 51_000);
+        assert_eq!(oracle.clamp_observation(40_000, 1), 49_000);
+        // the number of slots elapsed doesn't matter without scaling
+        assert_eq!(oracle.clamp_observation(60_000, 100), 51_000);
+    }
+
+    #[test]
+    fn test_clamp_observation_bps() {
+        let mut oracle = new_oracle(500_000, ObservationChangeMode::BasisPoints);
+
+        // 1% of 500_000
+        assert_eq!(oracle.max_observation_change(1), 5_000);
+        assert_eq!(oracle.clamp_observation(504_000, 1), 504_000);
+        assert_eq!(oracle.clamp_observation(600_000, 1), 505_000);
+        assert_eq!(oracle.clamp_observation(400_000, 1), 495_000);
+
+        // the same percentage applies at any price level
+        oracle.last_observation = 500;
+        assert_eq!(oracle.clamp_observation(600, 1), 505);
+        assert_eq!(oracle.clamp_observation(400, 1), 495);
+    }
+
+    #[test]
+    fn test_clamp_observation_bps_rounds_up() {
+        let mut oracle = new_oracle(99, ObservationChangeMode::BasisPoints);
+
+        // 1% of 99 is 0.99, which would otherwise freeze the observation
+        assert_eq!(oracle.max_observation_change(1), 1);
+        assert_eq!(oracle.clamp_observation(200, 1), 100);
+
+        oracle.last_observation = 0;
+        assert_eq!(oracle.max_observation_change(1), 0);
+    }
+
+    #[test]
+    fn test_clamp_observation_bps_scaled_by_slots() {
+        let mut oracle = new_oracle(500_000, ObservationChangeMode::BasisPoints);
+        oracle.max_observation_change_scaling_slots = 1_000;
+
+        assert_eq!(oracle.max_observation_change(1), 5_000);
+        assert_eq!(oracle.max_observation_change(10), 50_000);
+        assert_eq!(oracle.clamp_observation(600_000, 10), 550_000);
+        assert_eq!(oracle.clamp_observation(0, 1_000), 0);
+    }
+
+    #[test]
+    fn test_clamp_observation_lots_scaled_by_slots() {
+        let mut oracle = new_oracle(50_000, ObservationChangeMode::Lots);
+        oracle.max_observation_change_scaling_slots = 5;
+
+        assert_eq!(oracle.clamp_observation(60_000, 1), 51_000);
+        assert_eq!(oracle.clamp_observation(60_000, 3), 53_000);
+        assert_eq!(oracle.clamp_observation(40_000, 3), 47_000);
+        // capped at 5 slots worth of change
+        assert_eq!(oracle.clamp_observation(60_000, 5), 55_000);
+        assert_eq!(oracle.clamp_observation(60_000, 10_000), 55_000);
+        assert_eq!(oracle.clamp_observation(40_000, 10_000), 45_000);
+    }
+
+    #[test]
+    fn test_clamp_observation_scaling_with_zero_slots_elapsed() {
+        let mut oracle = new_oracle(50_000, ObservationChangeMode::Lots);
+        oracle.max_observation_change_scaling_slots = 5;
+
+        // never scale the change below a single update's worth
+        assert_eq!(oracle.max_observation_change(0), 1_000);
+    }
+
+    #[test]
+    fn test_clamp_observation_saturates() {
+        let mut oracle = new_oracle(u64::MAX - 1, ObservationChangeMode::BasisPoints);
+        oracle.max_observation_change_per_update_bps = u64::MAX;
+        oracle.max_observation_change_scaling_slots = u64::MAX;
+
+        assert_eq!(oracle.max_observation_change(u64::MAX), u64::MAX);
+        assert_eq!(oracle.clamp_observation(u64::MAX, u64::MAX), u64::MAX);
+        assert_eq!(oracle.clamp_observation(0, u64::MAX), 0);
+
+        let mut oracle = new_oracle(u64::MAX - 1, ObservationChangeMode::Lots);
+        oracle.max_observation_change_per_update_lots = u64::MAX;
+        oracle.max_observation_change_scaling_slots = u64::MAX;
+
+        assert_eq!(oracle.max_observation_change(u64::MAX), u64::MAX);
+
+        assert_eq!(oracle.clamp_observation(u64::MAX, 1), u64::MAX);
+        assert_eq!(oracle.clamp_observation(0, 1), 0);
+    }
+
+    #[test]
+    fn test_new_at() {
+        let mut args = new_args(100);
+
+        let oracle = TWAPOracle::new_at(&args, 50, 1_000);
+        assert!(oracle.is_warmed_up());
+        assert_eq!(oracle.initial_slot, 50);
+        assert_eq!(oracle.initial_timestamp, 1_000);
+        assert_eq!(oracle.observation_aggregator, 100);
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, None, 50).unwrap(), 100);
+        assert_eq!(oracle.twap(TWAPWeighting::Time, None, 1_010).unwrap(), 100);
+
+        args.start_delay_slots = 10;
+        let oracle = TWAPOracle::new_at(&args, 50, 1_000);
+        assert!(!oracle.is_warmed_up());
+        assert_eq!(oracle.observation_aggregator, 0);
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, None, 50).unwrap_err(),
+            error!(OpenBookTWAPError::TWAPNotStarted)
+        );
+    }
+
+    #[test]
+    fn test_observe_skips() {
+        let mut oracle = TWAPOracle::new_at(&new_args(100), 10, 10);
+
+        assert_eq!(
+            oracle.observe(10, 10, Some(100), Some(100)),
+            Err(SkipReason::SameSlot)
+        );
+
+        // an empty side still uses up the slot
+        assert_eq!(
+            oracle.observe(11, 11, None, Some(100)),
+            Err(SkipReason::EmptySide)
+        );
+        assert_eq!(oracle.last_observed_slot, 11);
+        assert_eq!(oracle.last_updated_slot, 10);
+        assert_eq!(
+            oracle.observe(11, 11, Some(100), Some(100)),
+            Err(SkipReason::SameSlot)
+        );
+
+        // a 100% spread
+        assert_eq!(
+            oracle.observe(12, 12, Some(100), Some(200)),
+            Err(SkipReason::SpreadTooWide)
+        );
+
+        oracle.end_slot = Some(20);
+        assert_eq!(
+            oracle.observe(21, 21, Some(100), Some(102)),
+            Err(SkipReason::Ended)
+        );
+
+        assert_eq!(
+            oracle.observe(20, 20, Some(100), Some(102)),
+            Ok(RecordedObservation {
+                best_bid: 100,
+                best_ask: 102,
+                spot_price: 101,
+                clamped_observation: 101,
+                weighted_observation: 1_010,
+            })
+        );
+        assert_eq!(oracle.observation_aggregator, 1_110);
+        assert_eq!(oracle.last_updated_slot, 20);
+        assert_eq!(oracle.num_checkpoints, 2);
+    }
+
+    #[test]
+    fn test_observe_clamps() {
+        let mut oracle = TWAPOracle::new_at(&new_args(10_000), 0, 0);
+
+        let recorded = oracle.observe(1, 1, Some(20_000), Some(20_000)).unwrap();
+        assert_eq!(recorded.spot_price, 20_000);
+        assert_eq!(recorded.clamped_observation, 11_000);
+
+        let recorded = oracle.observe(3, 2, Some(5_000), Some(5_000)).unwrap();
+        assert_eq!(recorded.clamped_observation, 10_000);
+        assert_eq!(recorded.weighted_observation, 20_000);
+
+        // 10_000 + 11_000 + 10_000 * 2
+        assert_eq!(oracle.observation_aggregator, 41_000);
+        // 10_000 + 11_000 + 10_000
+        assert_eq!(oracle.time_weighted_aggregator, 31_000);
+
+        // a timestamp going backwards adds no time-weighted observation
+        let recorded = oracle.observe(4, 1, Some(10_000), Some(10_000)).unwrap();
+        assert_eq!(recorded.weighted_observation, 10_000);
+        assert_eq!(oracle.time_weighted_aggregator, 31_000);
+        assert_eq!(oracle.last_updated_timestamp, 2);
+    }
+
+    #[test]
+    fn test_observe_warm_up() {
+        let mut args = new_args(100);
+        args.start_delay_slots = 10;
+        let mut oracle = TWAPOracle::new_at(&args, 0, 0);
+
+        let recorded = oracle.observe(5, 5, Some(600), Some(600)).unwrap();
+        assert_eq!(recorded.clamped_observation, 600);
+        assert_eq!(recorded.weighted_observation, 0);
+        assert!(!oracle.is_warmed_up());
+
+        let recorded = oracle.observe(10, 10, Some(2_000), Some(2_000)).unwrap();
+        assert_eq!(recorded.clamped_observation, 1_600);
+        assert_eq!(recorded.weighted_observation, 1_600);
+        assert!(oracle.is_warmed_up());
+        assert_eq!(oracle.initial_slot, 10);
+        assert_eq!(oracle.twap(TWAPWeighting::Slot, None, 10).unwrap(), 1_600);
+    }
+
+    #[test]
+    fn test_spread_bps() {
+        assert_eq!(TWAPOracle::spread_bps(100, 110), 1_000);
+        assert_eq!(TWAPOracle::spread_bps(100, 100), 0);
+        // a crossed book has no spread
+        assert_eq!(TWAPOracle::spread_bps(110, 100), 0);
+        assert_eq!(TWAPOracle::spread_bps(0, 100), u64::MAX);
+        assert_eq!(TWAPOracle::spread_bps(1, i64::MAX), u64::MAX);
+        // 20.79% rounds up to 2_080, not down to 2_079
+        assert_eq!(TWAPOracle::spread_bps(101, 122), 2_080);
+        assert_eq!(TWAPOracle::spread_bps(101, 121), 1_981);
+
+        // with the default `max_spread_bps`, the same spreads are too wide as
+        // under v0.2's fixed 20% rule
+        let oracle = new_oracle(100, ObservationChangeMode::Lots);
+        for best_bid in 1..=300 {
+            for best_ask in best_bid..=best_bid * 2 {
+                assert_eq!(
+                    oracle.spot_price(best_bid, best_ask).is_none(),
+                    best_ask > best_bid * 12 / 10,
+                    "bid {} ask {}",
+                    best_bid,
+                    best_ask
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_spot_price_wide_spread() {
+        let mut oracle = new_oracle(100, ObservationChangeMode::Lots);
+
+        assert_eq!(oracle.spot_price(100, 110), Some(105));
+        assert_eq!(oracle.spot_price(100, 200), None);
+
+        oracle.wide_spread_behavior = WideSpreadBehavior::ClampTowardLastObservation;
+        assert_eq!(oracle.spot_price(100, 110), Some(105));
+        assert_eq!(oracle.spot_price(100, 200), Some(100));
+        oracle.last_observation = 150;
+        assert_eq!(oracle.spot_price(100, 200), Some(150));
+        oracle.last_observation = 300;
+        assert_eq!(oracle.spot_price(100, 200), Some(200));
+
+        // the spread is 10_000 bps, so we only move 2_000 / 10_000 of the way
+        oracle.wide_spread_behavior = WideSpreadBehavior::DampenTowardMidpoint;
+        assert_eq!(oracle.spot_price(100, 200), Some(270));
+        oracle.last_observation = 100;
+        assert_eq!(oracle.spot_price(100, 200), Some(110));
+
+        // `clamp` would panic on a crossed book, whatever the behavior
+        for behavior in [
+            WideSpreadBehavior::Skip,
+            WideSpreadBehavior::ClampTowardLastObservation,
+            WideSpreadBehavior::DampenTowardMidpoint,
+        ] {
+            oracle.wide_spread_behavior = behavior;
+            assert_eq!(oracle.spot_price(200, 100), None);
+            assert_eq!(oracle.spot_price(0, 100), None);
+            assert_eq!(oracle.spot_price(-100, 100), None);
+        }
+    }
+
+    #[test]
+    fn test_observe_extreme_prices() {
+        let max_price = i64::MAX as u64;
+        let mut oracle = new_oracle(max_price, ObservationChangeMode::Lots);
+        oracle.push_checkpoint();
+
+        let recorded = oracle
+            .observe(1, 1, Some(i64::MAX - 1), Some(i64::MAX))
+            .unwrap();
+        assert_eq!(recorded.spot_price, max_price);
+
+        let recorded = oracle
+            .observe(u64::MAX, i64::MAX, Some(i64::MAX), Some(i64::MAX))
+            .unwrap();
+        assert_eq!(recorded.clamped_observation, max_price);
+
+        // the aggregators hold a maximal price over every slot
+        assert_eq!(oracle.observation_aggregator, max_price as u128 * (1 << 64));
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, None, u64::MAX).unwrap(),
+            max_price
+        );
+        assert_eq!(
+            oracle
+                .twap(TWAPWeighting::Time, None, i64::MAX as u64)
+                .unwrap(),
+            max_price
+        );
+    }
+
+    #[test]
+    fn test_observe_invariants() {
+        let mut rng = Rng(0x853c_49e6_748f_ea9b);
+
+        for _ in 0..100 {
+            let observation_change_mode = match rng.next() % 2 {
+                0 => ObservationChangeMode::Lots,
+                _ => ObservationChangeMode::BasisPoints,
+            };
+            let mut oracle = new_oracle(1_000_000, observation_change_mode);
+            oracle.max_observation_change_per_update_lots = rng.next() % 50_000;
+            oracle.max_observation_change_per_update_bps = rng.next() % 1_000;
+            oracle.max_observation_change_scaling_slots = rng.next() % 4;
+            oracle.wide_spread_behavior = match rng.next() % 3 {
+                0 => WideSpreadBehavior::Skip,
+                1 => WideSpreadBehavior::ClampTowardLastObservation,
+                _ => WideSpreadBehavior::DampenTowardMidpoint,
+            };
+            oracle.push_checkpoint();
+
+            let (mut slot, mut unix_ts) = (0, 0);
+            let (mut min_observation, mut max_observation) = (1_000_000, 1_000_000);
+
+            for _ in 0..200 {
+                slot += rng.next() % 5;
+                unix_ts += (rng.next() % 3) as i64;
+
+                let best_bid = 1 + (rng.next() % 2_000_000) as i64;
+                let best_ask = best_bid + (rng.next() % 1_000_000) as i64;
+                let (best_bid, best_ask) = match rng.next() % 10 {
+                    0 => (None, Some(best_ask)),
+                    1 => (Some(best_bid), None),
+                    _ => (Some(best_bid), Some(best_ask)),
+                };
+
+                let last_observation = oracle.last_observation;
+                let last_aggregator = oracle.observation_aggregator;
+                let max_change =
+                    oracle.max_observation_change(slot.saturating_sub(oracle.last_updated_slot));
+
+                if let Ok(recorded) = oracle.observe(slot, unix_ts, best_bid, best_ask) {
+                    let observation = recorded.clamped_observation;
+                    assert!(observation.abs_diff(last_observation) <= max_change);
+
+                    min_observation = std::cmp::min(min_observation, observation);
+                    max_observation = std::cmp::max(max_observation, observation);
+                }
+
+                assert!(oracle.observation_aggregator >= last_aggregator);
+
+                let twap = oracle.twap(TWAPWeighting::Slot, None, slot).unwrap();
+                assert!(min_observation <= twap && twap <= max_observation);
+            }
+        }
+    }
+}
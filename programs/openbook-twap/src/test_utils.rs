@@ -0,0 +1,64 @@
+use crate::*;
+
+pub(crate) fn new_oracle(
+    last_observation: u64,
+    observation_change_mode: ObservationChangeMode,
+) -> TWAPOracle {
+    TWAPOracle {
+        expected_value: last_observation,
+        initial_slot: 0,
+        start_delay_slots: 0,
+        end_slot: None,
+        post_end_trading: PostEndTrading::Continue,
+        final_twap: None,
+        last_updated_slot: 0,
+        last_observed_slot: 0,
+        last_observation,
+        observation_aggregator: last_observation as u128,
+        initial_timestamp: 0,
+        last_updated_timestamp: 0,
+        time_weighted_aggregator: last_observation as u128,
+        accumulate_log_observations: false,
+        log_observation_aggregator: 0,
+        max_observation_change_per_update_lots: 1_000,
+        max_observation_change_per_update_bps: 100,
+        observation_change_mode,
+        max_observation_change_scaling_slots: 0,
+        max_spread_bps: 2_000,
+        wide_spread_behavior: WideSpreadBehavior::Skip,
+        checkpoint_interval_slots: 1,
+        num_checkpoints: 0,
+        checkpoints: [TWAPCheckpoint::default(); MAX_CHECKPOINTS],
+        end_timestamp: None,
+        reserved: [0; 55],
+    }
+}
+
+// records `observation` the same way `update_oracle` does, minus the clamping
+pub(crate) fn record(oracle: &mut TWAPOracle, slot: u64, unix_timestamp: i64, observation: u64) {
+    oracle.observation_aggregator +=
+        observation as u128 * (slot - oracle.last_updated_slot) as u128;
+    oracle.time_weighted_aggregator +=
+        observation as u128 * (unix_timestamp - oracle.last_updated_timestamp) as u128;
+    oracle.last_updated_slot = slot;
+    oracle.last_updated_timestamp = unix_timestamp;
+    oracle.last_observation = observation;
+    oracle.push_checkpoint();
+}
+
+pub(crate) fn new_args(expected_value: u64) -> CreateTWAPMarketArgs {
+    CreateTWAPMarketArgs {
+        expected_value,
+        max_observation_change_per_update_lots: 1_000,
+        max_observation_change_per_update_bps: 0,
+        observation_change_mode: ObservationChangeMode::Lots,
+        max_observation_change_scaling_slots: 0,
+        max_spread_bps: 2_000,
+        wide_spread_behavior: WideSpreadBehavior::Skip,
+        checkpoint_interval_slots: 1,
+        accumulate_log_observations: false,
+        start_delay_slots: 0,
+        end_slot: None,
+        post_end_trading: PostEndTrading::Continue,
+    }
+}
@@ -0,0 +1,460 @@
+use anchor_lang::prelude::*;
+
+use crate::*;
+
+pub(crate) const TWAP_MARKET: &[u8] = b"twap_market";
+
+/// v0.2 accounts, which have no version field, are version 0
+pub const TWAP_MARKET_VERSION: u8 = 1;
+
+/// New fields take the place of reserved bytes, so this doesn't change
+pub const TWAP_MARKET_SPACE: usize = 8 + TWAPMarket::SIZE;
+
+pub const TWAP_MARKET_V0_2_SPACE: usize = 144;
+
+#[account]
+pub struct TWAPMarket {
+    pub version: u8,
+    pub market: Pubkey,
+    pub pda_bump: u8,
+    pub twap_oracle: TWAPOracle,
+    pub close_market_rent_receiver: Pubkey,
+    /// When set, `close_market` requires this account's signature
+    pub close_authority: Option<Pubkey>,
+    /// The market can't be closed until its `DecisionPair`s are resolved
+    pub unresolved_decisions: u64,
+    pub reserved: [u8; 120],
+}
+
+impl TWAPMarket {
+    pub const SIZE: usize = 1 + 32 + 1 + TWAPOracle::SIZE + 32 + (1 + 32) + 8 + 120;
+
+    pub fn get_twap_market_seeds<'a>(market: &'a Pubkey, bump: &'a u8) -> [&'a [u8]; 3] {
+        [TWAP_MARKET, market.as_ref(), bytemuck::bytes_of(bump)]
+    }
+
+    pub(crate) fn init(
+        &mut self,
+        market: Pubkey,
+        pda_bump: u8,
+        payer: Pubkey,
+        args: &CreateTWAPMarketArgs,
+    ) -> Result<()> {
+        require!(
+            args.expected_value > 0 || args.observation_change_mode == ObservationChangeMode::Lots,
+            OpenBookTWAPError::InvalidExpectedValue
+        );
+        require!(
+            args.checkpoint_interval_slots >= MIN_CHECKPOINT_INTERVAL_SLOTS,
+            OpenBookTWAPError::InvalidCheckpointInterval
+        );
+        require!(
+            args.max_observation_change_per_update_bps < 10_000
+                && (args.max_observation_change_per_update_bps > 0
+                    || args.observation_change_mode != ObservationChangeMode::BasisPoints),
+            OpenBookTWAPError::InvalidMaxObservationChangeBps
+        );
+
+        self.version = TWAP_MARKET_VERSION;
+        self.pda_bump = pda_bump;
+        self.market = market;
+        self.twap_oracle.init(args);
+
+        let oracle = &self.twap_oracle;
+        if let Some(end_slot) = oracle.end_slot {
+            require!(
+                end_slot >= oracle.initial_slot.saturating_add(oracle.start_delay_slots),
+                OpenBookTWAPError::InvalidEndSlot
+            );
+        }
+        self.close_market_rent_receiver = payer;
+        self.close_authority = Some(payer);
+
+        Ok(())
+    }
+
+    pub fn layout_version(data: &[u8]) -> Result<u8> {
+        require!(
+            data.len() >= 8 && data[..8] == Self::DISCRIMINATOR,
+            ErrorCode::AccountDiscriminatorMismatch
+        );
+
+        if data.len() == TWAP_MARKET_V0_2_SPACE {
+            Ok(0)
+        } else if data.len() >= TWAP_MARKET_SPACE {
+            Ok(data[8])
+        } else {
+            err!(ErrorCode::AccountDidNotDeserialize)
+        }
+    }
+
+    /// `data` must already have grown to `TWAP_MARKET_SPACE`
+    pub fn migrate_v0_2(data: &mut [u8], unix_timestamp: i64) -> Result<()> {
+        require!(
+            data.len() == TWAP_MARKET_SPACE && data[..8] == Self::DISCRIMINATOR,
+            ErrorCode::AccountDidNotDeserialize
+        );
+
+        let legacy = TWAPMarketV0_2::deserialize(&mut &data[8..])?;
+        let oracle = &legacy.twap_oracle;
+
+        // a whole `TWAPMarket` is too large for the stack, so we write the
+        // new layout field by field. Zeroed, the fields we skip over are all
+        // zero or `None`.
+        data[8..].fill(0);
+        let mut writer = &mut data[8..];
+
+        TWAP_MARKET_VERSION.serialize(&mut writer)?;
+        legacy.market.serialize(&mut writer)?;
+        legacy.pda_bump.serialize(&mut writer)?;
+
+        oracle.expected_value.serialize(&mut writer)?;
+        oracle.initial_slot.serialize(&mut writer)?;
+        0u64.serialize(&mut writer)?; // start_delay_slots
+        None::<u64>.serialize(&mut writer)?; // end_slot
+        PostEndTrading::Continue.serialize(&mut writer)?;
+        None::<u64>.serialize(&mut writer)?; // final_twap
+        oracle.last_updated_slot.serialize(&mut writer)?;
+        oracle.last_observed_slot.serialize(&mut writer)?;
+        oracle.last_observation.serialize(&mut writer)?;
+        oracle.observation_aggregator.serialize(&mut writer)?;
+        // v0.2 didn't track time, so the time-weighted TWAP starts now
+        unix_timestamp.serialize(&mut writer)?; // initial_timestamp
+        unix_timestamp.serialize(&mut writer)?; // last_updated_timestamp
+        let time_weighted_aggregator = oracle.last_observation as u128;
+        time_weighted_aggregator.serialize(&mut writer)?;
+        false.serialize(&mut writer)?; // accumulate_log_observations
+        0u128.serialize(&mut writer)?; // log_observation_aggregator
+        oracle
+            .max_observation_change_per_update_lots
+            .serialize(&mut writer)?;
+        0u64.serialize(&mut writer)?; // max_observation_change_per_update_bps
+        ObservationChangeMode::Lots.serialize(&mut writer)?;
+        0u64.serialize(&mut writer)?; // max_observation_change_scaling_slots
+        2_000u64.serialize(&mut writer)?; // max_spread_bps, v0.2 skipped spreads wider than 20%
+        WideSpreadBehavior::Skip.serialize(&mut writer)?;
+        MIN_CHECKPOINT_INTERVAL_SLOTS.serialize(&mut writer)?;
+        1u64.serialize(&mut writer)?; // num_checkpoints
+        TWAPCheckpoint {
+            slot: oracle.last_updated_slot,
+            unix_timestamp,
+            observation_aggregator: oracle.observation_aggregator,
+            time_weighted_aggregator,
+            log_observation_aggregator: 0,
+            last_observation: oracle.last_observation,
+        }
+        .serialize(&mut writer)?;
+        for _ in 1..MAX_CHECKPOINTS {
+            TWAPCheckpoint::default().serialize(&mut writer)?;
+        }
+        None::<i64>.serialize(&mut writer)?; // end_timestamp
+        [0u8; 55].serialize(&mut writer)?; // twap_oracle.reserved
+
+        legacy.close_market_rent_receiver.serialize(&mut writer)?;
+        // anyone could close v0.2 markets
+        None::<Pubkey>.serialize(&mut writer)?; // close_authority
+
+        Ok(())
+    }
+
+    /// The slot-weighted TWAP up to `current_slot`, or up to the `end_slot` once it
+    /// has passed, for other programs to call on a deserialized `TWAPMarket`
+    pub fn twap_since_start(&self, current_slot: u64) -> Result<u64> {
+        let end = match self.twap_oracle.end_slot {
+            Some(end_slot) => std::cmp::min(current_slot, end_slot),
+            None => current_slot,
+        };
+
+        self.twap_oracle.twap(TWAPWeighting::Slot, None, end)
+    }
+
+    /// The slot-weighted TWAP between two checkpoints of this market's oracle
+    pub fn twap_between(
+        checkpoint_a: &TWAPCheckpoint,
+        checkpoint_b: &TWAPCheckpoint,
+    ) -> Result<u64> {
+        require!(
+            checkpoint_b.slot > checkpoint_a.slot,
+            OpenBookTWAPError::EmptyTWAPWindow
+        );
+
+        let aggregated = checkpoint_b
+            .observation_aggregator
+            .checked_sub(checkpoint_a.observation_aggregator)
+            .ok_or_else(|| error!(OpenBookTWAPError::InvalidTWAPWindow))?;
+        let twap = aggregated / (checkpoint_b.slot - checkpoint_a.slot) as u128;
+
+        u64::try_from(twap).map_err(|_| error!(OpenBookTWAPError::MathOverflow))
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct TWAPMarketV0_2 {
+    pub market: Pubkey,
+    pub pda_bump: u8,
+    pub twap_oracle: TWAPOracleV0_2,
+    pub close_market_rent_receiver: Pubkey,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct TWAPOracleV0_2 {
+    pub expected_value: u64,
+    pub initial_slot: u64,
+    pub last_updated_slot: u64,
+    pub last_observed_slot: u64,
+    pub last_observation: u64,
+    pub observation_aggregator: u128,
+    pub max_observation_change_per_update_lots: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use anchor_lang::Discriminator;
+
+    // a `TWAPMarket` as v0.2 wrote it, padded to the space v0.2 allocated
+    fn v0_2_account_data(market: Pubkey, close_market_rent_receiver: Pubkey) -> Vec<u8> {
+        let mut data = TWAPMarket::DISCRIMINATOR.to_vec();
+        data.extend_from_slice(market.as_ref());
+        data.push(254); // pda_bump
+        data.extend_from_slice(&500_000u64.to_le_bytes()); // expected_value
+        data.extend_from_slice(&100u64.to_le_bytes()); // initial_slot
+        data.extend_from_slice(&150u64.to_le_bytes()); // last_updated_slot
+        data.extend_from_slice(&160u64.to_le_bytes()); // last_observed_slot
+        data.extend_from_slice(&510_000u64.to_le_bytes()); // last_observation
+        data.extend_from_slice(&25_500_000u128.to_le_bytes()); // observation_aggregator
+        data.extend_from_slice(&10_000u64.to_le_bytes()); // max_observation_change_per_update_lots
+        data.extend_from_slice(close_market_rent_receiver.as_ref());
+        data.resize(144, 0);
+        data
+    }
+
+    #[test]
+    fn test_migrate_v0_2() {
+        let market = Pubkey::new_unique();
+        let close_market_rent_receiver = Pubkey::new_unique();
+        let mut data = v0_2_account_data(market, close_market_rent_receiver);
+
+        // v0.2 accounts don't deserialize with the current layout
+        assert!(TWAPMarket::try_deserialize(&mut &data[..]).is_err());
+        assert_eq!(TWAPMarket::layout_version(&data).unwrap(), 0);
+
+        data.resize(TWAP_MARKET_SPACE, 0);
+        TWAPMarket::migrate_v0_2(&mut data, 1_000).unwrap();
+
+        assert_eq!(
+            TWAPMarket::layout_version(&data).unwrap(),
+            TWAP_MARKET_VERSION
+        );
+        let migrated = TWAPMarket::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(migrated.version, TWAP_MARKET_VERSION);
+        assert_eq!(migrated.market, market);
+        assert_eq!(migrated.pda_bump, 254);
+        assert_eq!(
+            migrated.close_market_rent_receiver,
+            close_market_rent_receiver
+        );
+        assert_eq!(migrated.close_authority, None);
+
+        let oracle = &migrated.twap_oracle;
+        assert_eq!(oracle.expected_value, 500_000);
+        assert_eq!(oracle.initial_slot, 100);
+        assert_eq!(oracle.last_updated_slot, 150);
+        assert_eq!(oracle.last_observed_slot, 160);
+        assert_eq!(oracle.last_observation, 510_000);
+        assert_eq!(oracle.observation_aggregator, 25_500_000);
+        assert_eq!(oracle.max_observation_change_per_update_lots, 10_000);
+        assert_eq!(oracle.observation_change_mode, ObservationChangeMode::Lots);
+        assert_eq!(oracle.initial_timestamp, 1_000);
+        assert_eq!(oracle.max_spread_bps, 2_000);
+        assert_eq!(oracle.wide_spread_behavior, WideSpreadBehavior::Skip);
+        assert_eq!(
+            oracle.checkpoint_interval_slots,
+            MIN_CHECKPOINT_INTERVAL_SLOTS
+        );
+        assert!(oracle.is_warmed_up());
+        assert_eq!(oracle.num_checkpoints, 1);
+        assert_eq!(oracle.latest_checkpoint().slot, 150);
+        assert_eq!(oracle.latest_checkpoint().unix_timestamp, 1_000);
+        assert_eq!(
+            oracle.latest_checkpoint().observation_aggregator,
+            25_500_000
+        );
+
+        // every byte was written where the current layout expects it
+        let mut serialized = vec![];
+        migrated.try_serialize(&mut serialized).unwrap();
+        serialized.resize(TWAP_MARKET_SPACE, 0);
+        assert_eq!(data, serialized);
+
+        // 25_500_000 / (150 - 100 + 1)
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, None, 150).unwrap(),
+            500_000
+        );
+        // past the last update, the last observation is extrapolated
+        assert_eq!(
+            oracle.twap(TWAPWeighting::Slot, Some(150), 160).unwrap(),
+            510_000
+        );
+    }
+
+    #[test]
+    fn test_migrate_rejects_other_accounts() {
+        let mut data = v0_2_account_data(Pubkey::new_unique(), Pubkey::new_unique());
+        data[0] ^= 1;
+
+        assert_eq!(
+            TWAPMarket::layout_version(&data).err(),
+            Some(error!(ErrorCode::AccountDiscriminatorMismatch))
+        );
+
+        // a `TWAPMarket` of neither layout's size
+        data[0] ^= 1;
+        data.push(0);
+        assert_eq!(
+            TWAPMarket::layout_version(&data).err(),
+            Some(error!(ErrorCode::AccountDidNotDeserialize))
+        );
+
+        // accounts at least as large as the current layout carry their version
+        data.resize(TWAP_MARKET_SPACE + 100, 0);
+        data[8] = 7;
+        assert_eq!(TWAPMarket::layout_version(&data).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_twap_market_size() {
+        let mut twap_market = new_twap_market(TWAPOracle::new_at(&new_args(100), 0, 0));
+        twap_market.twap_oracle.end_slot = Some(10);
+        twap_market.twap_oracle.final_twap = Some(100);
+        twap_market.twap_oracle.end_timestamp = Some(10);
+        twap_market.close_authority = Some(Pubkey::new_unique());
+
+        let mut data = vec![];
+        twap_market.try_serialize(&mut data).unwrap();
+        assert_eq!(data.len(), TWAP_MARKET_SPACE);
+        assert_eq!(
+            TWAPMarket::layout_version(&data).unwrap(),
+            TWAP_MARKET_VERSION
+        );
+
+        assert_eq!(
+            TWAPCheckpoint::SIZE,
+            TWAPCheckpoint::default().try_to_vec().unwrap().len()
+        );
+    }
+
+    fn new_twap_market(twap_oracle: TWAPOracle) -> TWAPMarket {
+        TWAPMarket {
+            version: TWAP_MARKET_VERSION,
+            market: Pubkey::new_unique(),
+            pda_bump: 255,
+            twap_oracle,
+            close_market_rent_receiver: Pubkey::new_unique(),
+            close_authority: None,
+            unresolved_decisions: 0,
+            reserved: [0; 120],
+        }
+    }
+
+    #[test]
+    fn test_init_checks_max_observation_change_bps() {
+        let mut twap_market = new_twap_market(TWAPOracle::new_at(&new_args(100), 0, 0));
+        let mut args = new_args(100);
+        args.checkpoint_interval_slots = MIN_CHECKPOINT_INTERVAL_SLOTS;
+
+        for (bps, mode) in [
+            (10_000, ObservationChangeMode::Lots),
+            (10_000, ObservationChangeMode::BasisPoints),
+            (u64::MAX, ObservationChangeMode::BasisPoints),
+            (0, ObservationChangeMode::BasisPoints),
+        ] {
+            args.max_observation_change_per_update_bps = bps;
+            args.observation_change_mode = mode;
+            assert_eq!(
+                twap_market
+                    .init(Pubkey::new_unique(), 255, Pubkey::new_unique(), &args)
+                    .unwrap_err(),
+                error!(OpenBookTWAPError::InvalidMaxObservationChangeBps)
+            );
+        }
+    }
+
+    #[test]
+    fn test_twap_since_start() {
+        let mut oracle = new_oracle(100, ObservationChangeMode::Lots);
+        oracle.push_checkpoint();
+        record(&mut oracle, 10, 10, 200);
+
+        let mut twap_market = new_twap_market(oracle);
+
+        // (100 + 200 * 10) / 11
+        assert_eq!(twap_market.twap_since_start(10).unwrap(), 190);
+        // (100 + 200 * 20) / 21, extrapolating the last observation
+        assert_eq!(twap_market.twap_since_start(20).unwrap(), 195);
+
+        // nothing past the end slot counts
+        twap_market.twap_oracle.end_slot = Some(20);
+        assert_eq!(twap_market.twap_since_start(1_000).unwrap(), 195);
+
+        twap_market.twap_oracle.initial_slot = 5;
+        assert_eq!(
+            twap_market.twap_since_start(4).unwrap_err(),
+            error!(OpenBookTWAPError::TWAPWindowPrecedesMarket)
+        );
+
+        twap_market.twap_oracle.num_checkpoints = 0;
+        assert_eq!(
+            twap_market.twap_since_start(10).unwrap_err(),
+            error!(OpenBookTWAPError::TWAPNotStarted)
+        );
+    }
+
+    #[test]
+    fn test_twap_between() {
+        let mut oracle = new_oracle(100, ObservationChangeMode::Lots);
+        oracle.push_checkpoint();
+        record(&mut oracle, 10, 10, 200);
+        record(&mut oracle, 20, 20, 300);
+
+        let checkpoints: Vec<_> = oracle.checkpoints().copied().collect();
+        assert_eq!(
+            TWAPMarket::twap_between(&checkpoints[0], &checkpoints[1]).unwrap(),
+            200
+        );
+        assert_eq!(
+            TWAPMarket::twap_between(&checkpoints[1], &checkpoints[2]).unwrap(),
+            300
+        );
+        assert_eq!(
+            TWAPMarket::twap_between(&checkpoints[0], &checkpoints[2]).unwrap(),
+            250
+        );
+
+        assert_eq!(
+            TWAPMarket::twap_between(&checkpoints[1], &checkpoints[1]).unwrap_err(),
+            error!(OpenBookTWAPError::EmptyTWAPWindow)
+        );
+        assert_eq!(
+            TWAPMarket::twap_between(&checkpoints[2], &checkpoints[1]).unwrap_err(),
+            error!(OpenBookTWAPError::EmptyTWAPWindow)
+        );
+
+        // an aggregator that went backwards can't come from the same oracle
+        let mut corrupted = checkpoints[2];
+        corrupted.observation_aggregator = 0;
+        assert_eq!(
+            TWAPMarket::twap_between(&checkpoints[1], &corrupted).unwrap_err(),
+            error!(OpenBookTWAPError::InvalidTWAPWindow)
+        );
+
+        // a TWAP that doesn't fit in a u64
+        corrupted.observation_aggregator = u128::MAX;
+        assert_eq!(
+            TWAPMarket::twap_between(&checkpoints[1], &corrupted).unwrap_err(),
+            error!(OpenBookTWAPError::MathOverflow)
+        );
+    }
+}
@@ -0,0 +1,988 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::entrypoint::ProgramResult;
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::solana_program::{bpf_loader, system_program};
+use anchor_lang::{AccountDeserialize, InstructionData};
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::token::spl_token;
+use openbook_twap::{
+    CreateTWAPMarketArgs, ObservationChangeMode, OpenBookTWAPError, PlaceOrderArgs, PlaceOrderType,
+    PlaceTakeOrderArgs, PostEndTrading, SelfTradeBehavior, Side, TWAPMarket, TWAPWeighting,
+    WideSpreadBehavior, MIN_CHECKPOINT_INTERVAL_SLOTS,
+};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::account::Account;
+use solana_sdk::instruction::{Instruction, InstructionError};
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+pub const EXPECTED_VALUE: u64 = 50 * 10_000;
+pub const MAX_UPDATE_LOTS: u64 = 10_000;
+pub const MAX_SPREAD_BPS: u64 = 2_000;
+
+pub const QUOTE_LOT_SIZE: i64 = 100;
+pub const BASE_LOT_SIZE: i64 = 1_000_000_000;
+
+pub const BASE_DECIMALS: u8 = 9;
+pub const QUOTE_DECIMALS: u8 = 6;
+
+pub const BASE_AMOUNT: u64 = 100 * 10u64.pow(BASE_DECIMALS as u32);
+pub const QUOTE_AMOUNT: u64 = 1_000 * 10u64.pow(QUOTE_DECIMALS as u32);
+
+/// The market expires this many seconds after it's created, so that tests
+/// can prune, settle and close it
+pub const MARKET_DURATION_SECONDS: i64 = 1_000;
+
+pub fn twap_market_args() -> CreateTWAPMarketArgs {
+    CreateTWAPMarketArgs {
+        expected_value: EXPECTED_VALUE,
+        max_observation_change_per_update_lots: MAX_UPDATE_LOTS,
+        max_observation_change_per_update_bps: 0,
+        observation_change_mode: ObservationChangeMode::Lots,
+        max_observation_change_scaling_slots: 0,
+        max_spread_bps: MAX_SPREAD_BPS,
+        wide_spread_behavior: WideSpreadBehavior::Skip,
+        checkpoint_interval_slots: MIN_CHECKPOINT_INTERVAL_SLOTS,
+        accumulate_log_observations: false,
+        start_delay_slots: 0,
+        end_slot: None,
+        post_end_trading: PostEndTrading::Continue,
+    }
+}
+
+/// A limit order for a single base lot
+pub fn limit_order(side: Side, price_lots: i64, client_order_id: u64) -> PlaceOrderArgs {
+    PlaceOrderArgs {
+        side,
+        price_lots,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: price_lots,
+        client_order_id,
+        order_type: PlaceOrderType::Limit,
+        expiry_timestamp: 0,
+        self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        limit: 255,
+    }
+}
+
+pub fn anchor_error(error: OpenBookTWAPError) -> u32 {
+    error.into()
+}
+
+/// The custom error code a failed single-instruction transaction returned
+pub fn error_code(error: BanksClientError) -> Option<u32> {
+    match error.unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(code),
+        _ => None,
+    }
+}
+
+// anchor's entrypoint ties the lifetime of the account infos to that of the
+// slice holding them, which `processor!` doesn't allow
+fn process_instruction<'a, 'b, 'c, 'd>(
+    program_id: &'a Pubkey,
+    accounts: &'b [AccountInfo<'c>],
+    data: &'d [u8],
+) -> ProgramResult {
+    // SAFETY: the slice outlives the call, and the program doesn't hold on
+    // to the account infos once it returns
+    let accounts: &'c [AccountInfo<'c>] = unsafe { std::mem::transmute(accounts) };
+
+    openbook_twap::entry(program_id, accounts, data)
+}
+
+pub struct TestMarket {
+    pub market: Keypair,
+    pub twap_market: Pubkey,
+    pub market_authority: Pubkey,
+    pub event_authority: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub event_heap: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub market_base_vault: Pubkey,
+    pub market_quote_vault: Pubkey,
+}
+
+pub struct TestUser {
+    pub owner: Keypair,
+    pub base_account: Pubkey,
+    pub quote_account: Pubkey,
+    pub open_orders_indexer: Pubkey,
+    pub open_orders_account: Pubkey,
+}
+
+pub struct TestContext {
+    pub context: ProgramTestContext,
+    pub market: TestMarket,
+    /// Another market over the same mints, for tests that need two, such as
+    /// decisions. It's only created by `create_second_market`.
+    pub second_market: TestMarket,
+    pub user: TestUser,
+}
+
+impl TestContext {
+    /// Starts a bank with openbook loaded from `tests/fixtures`, two mints,
+    /// a funded user and the accounts two markets need allocated up front.
+    /// The markets themselves are created by `create_market` and
+    /// `create_second_market`.
+    pub async fn start() -> Self {
+        let mut program_test = ProgramTest::new(
+            "openbook_twap",
+            openbook_twap::ID,
+            processor!(process_instruction),
+        );
+        program_test.set_compute_max_units(1_400_000);
+
+        let openbook = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../tests/fixtures/openbook_v2.so"
+        ))
+        .expect(
+            "tests/fixtures/openbook_v2.so is missing, dump it from the repository root with \
+             `solana program dump -um opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb \
+             tests/fixtures/openbook_v2.so`",
+        );
+        add_account(
+            &mut program_test,
+            openbook_v2::ID,
+            openbook,
+            bpf_loader::id(),
+            true,
+        );
+
+        let mint_authority = Pubkey::new_unique();
+        let base_mint = add_mint(&mut program_test, mint_authority, BASE_DECIMALS);
+        let quote_mint = add_mint(&mut program_test, mint_authority, QUOTE_DECIMALS);
+
+        let market = add_market(&mut program_test, base_mint, quote_mint);
+        let second_market = add_market(&mut program_test, base_mint, quote_mint);
+
+        // the owner pays openbook's penalties, if any
+        let owner = Keypair::new();
+        program_test.add_account(
+            owner.pubkey(),
+            Account::new(10_000_000_000, 0, &system_program::ID),
+        );
+        let base_account =
+            add_token_account(&mut program_test, base_mint, owner.pubkey(), BASE_AMOUNT);
+        let quote_account =
+            add_token_account(&mut program_test, quote_mint, owner.pubkey(), QUOTE_AMOUNT);
+        let (open_orders_indexer, _) = Pubkey::find_program_address(
+            &[b"OpenOrdersIndexer", owner.pubkey().as_ref()],
+            &openbook_v2::ID,
+        );
+        let (open_orders_account, _) = Pubkey::find_program_address(
+            &[b"OpenOrders", owner.pubkey().as_ref(), &1u32.to_le_bytes()],
+            &openbook_v2::ID,
+        );
+
+        let context = program_test.start_with_context().await;
+
+        Self {
+            context,
+            market,
+            second_market,
+            user: TestUser {
+                owner,
+                base_account,
+                quote_account,
+                open_orders_indexer,
+                open_orders_account,
+            },
+        }
+    }
+
+    pub async fn clock(&mut self) -> Clock {
+        self.context.banks_client.get_sysvar().await.unwrap()
+    }
+
+    /// Warps ahead and fetches a new blockhash, so that repeating a
+    /// transaction from an earlier slot isn't rejected as a duplicate
+    pub async fn advance_slots(&mut self, slots: u64) {
+        let slot = self.clock().await.slot;
+
+        self.context.warp_to_slot(slot + slots).unwrap();
+        self.context.get_new_latest_blockhash().await.unwrap();
+    }
+
+    pub async fn set_unix_timestamp(&mut self, unix_timestamp: i64) {
+        let mut clock = self.clock().await;
+        clock.unix_timestamp = unix_timestamp;
+
+        self.context.set_sysvar(&clock);
+    }
+
+    pub async fn send(
+        &mut self,
+        instruction: Instruction,
+    ) -> std::result::Result<(), BanksClientError> {
+        send(&mut self.context, instruction, &[&self.user.owner]).await
+    }
+
+    /// Like `send`, but signed by `signers` instead of the user
+    pub async fn send_signed(
+        &mut self,
+        instruction: Instruction,
+        signers: &[&Keypair],
+    ) -> std::result::Result<(), BanksClientError> {
+        send(&mut self.context, instruction, signers).await
+    }
+
+    /// Simulates `get_twap` and decodes the TWAP it returns
+    pub async fn get_twap(
+        &mut self,
+        twap_market: Pubkey,
+        weighting: TWAPWeighting,
+        start: Option<u64>,
+        end: Option<u64>,
+    ) -> std::result::Result<u64, BanksClientError> {
+        let instruction = Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::GetTWAP { twap_market }.to_account_metas(None),
+            data: openbook_twap::instruction::GetTwap {
+                weighting,
+                start,
+                end,
+            }
+            .data(),
+        };
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&self.context.payer.pubkey()),
+            &[&self.context.payer],
+            self.context.last_blockhash,
+        );
+
+        let simulation = self
+            .context
+            .banks_client
+            .simulate_transaction(transaction)
+            .await?;
+        if let Some(Err(error)) = simulation.result {
+            return Err(BanksClientError::TransactionError(error));
+        }
+
+        let return_data = simulation
+            .simulation_details
+            .unwrap()
+            .return_data
+            .unwrap();
+        assert_eq!(return_data.program_id, openbook_twap::ID);
+
+        Ok(u64::from_le_bytes(return_data.data[..].try_into().unwrap()))
+    }
+
+    pub async fn account<T: AccountDeserialize>(&mut self, address: Pubkey) -> Option<T> {
+        let account = self
+            .context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()?;
+
+        Some(T::try_deserialize(&mut &account.data[..]).unwrap())
+    }
+
+    pub async fn twap_market(&mut self) -> Option<TWAPMarket> {
+        self.account(self.market.twap_market).await
+    }
+
+    /// Stores `data` at `address` as an account of this program, such as a
+    /// TWAP market in an older layout
+    pub fn set_program_account(&mut self, address: Pubkey, data: Vec<u8>) {
+        self.context.set_account(
+            &address,
+            &Account {
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                owner: openbook_twap::ID,
+                executable: false,
+                rent_epoch: 0,
+            }
+            .into(),
+        );
+    }
+
+    pub async fn token_balance(&mut self, token_account: Pubkey) -> u64 {
+        let account = self
+            .context
+            .banks_client
+            .get_account(token_account)
+            .await
+            .unwrap()
+            .unwrap();
+
+        spl_token::state::Account::unpack(&account.data)
+            .unwrap()
+            .amount
+    }
+
+    /// Creates the market and its TWAP market in one instruction, then the
+    /// user's open orders account, into which the whole balance is deposited
+    pub async fn create_market(&mut self, args: CreateTWAPMarketArgs) {
+        let time_expiry = self.clock().await.unix_timestamp + MARKET_DURATION_SECONDS;
+
+        let instruction = self.create_market_instruction(&self.market, time_expiry, args);
+        send(&mut self.context, instruction, &[&self.market.market])
+            .await
+            .unwrap();
+
+        let instruction = Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::CreateOpenOrdersAccount {
+                payer: self.context.payer.pubkey(),
+                owner: self.user.owner.pubkey(),
+                open_orders_indexer: self.user.open_orders_indexer,
+                open_orders_account: self.user.open_orders_account,
+                twap_market: self.market.twap_market,
+                market: self.market.market.pubkey(),
+                openbook_program: openbook_v2::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::CreateOpenOrdersAccount {
+                name: "oo".to_string(),
+            }
+            .data(),
+        };
+        self.send(instruction).await.unwrap();
+
+        self.send(self.deposit_instruction(BASE_AMOUNT / 2, QUOTE_AMOUNT / 2))
+            .await
+            .unwrap();
+    }
+
+    /// Creates the second market and its TWAP market, without any orders
+    pub async fn create_second_market(&mut self, args: CreateTWAPMarketArgs) {
+        let time_expiry = self.clock().await.unix_timestamp + MARKET_DURATION_SECONDS;
+
+        let instruction = self.create_market_instruction(&self.second_market, time_expiry, args);
+        send(&mut self.context, instruction, &[&self.second_market.market])
+            .await
+            .unwrap();
+    }
+
+    fn create_market_instruction(
+        &self,
+        m: &TestMarket,
+        time_expiry: i64,
+        args: CreateTWAPMarketArgs,
+    ) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::CreateMarketWithTWAP {
+                market: m.market.pubkey(),
+                twap_market: m.twap_market,
+                market_authority: m.market_authority,
+                bids: m.bids,
+                asks: m.asks,
+                event_heap: m.event_heap,
+                market_base_vault: m.market_base_vault,
+                market_quote_vault: m.market_quote_vault,
+                base_mint: m.base_mint,
+                quote_mint: m.quote_mint,
+                event_authority: m.event_authority,
+                payer: self.context.payer.pubkey(),
+                token_program: spl_token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                openbook_program: openbook_v2::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::CreateMarketWithTwap {
+                name: "META/USDC".to_string(),
+                quote_lot_size: QUOTE_LOT_SIZE,
+                base_lot_size: BASE_LOT_SIZE,
+                time_expiry,
+                args,
+            }
+            .data(),
+        }
+    }
+
+    fn deposit_accounts(&self) -> Vec<AccountMeta> {
+        openbook_twap::accounts::Deposit {
+            owner: self.user.owner.pubkey(),
+            user_base_account: self.user.base_account,
+            user_quote_account: self.user.quote_account,
+            open_orders_account: self.user.open_orders_account,
+            twap_market: self.market.twap_market,
+            market: self.market.market.pubkey(),
+            bids: Some(self.market.bids),
+            asks: Some(self.market.asks),
+            market_base_vault: self.market.market_base_vault,
+            market_quote_vault: self.market.market_quote_vault,
+            token_program: spl_token::ID,
+            openbook_program: openbook_v2::ID,
+        }
+        .to_account_metas(None)
+    }
+
+    pub fn deposit_instruction(&self, base_amount: u64, quote_amount: u64) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: self.deposit_accounts(),
+            data: openbook_twap::instruction::Deposit {
+                base_amount,
+                quote_amount,
+            }
+            .data(),
+        }
+    }
+
+    pub fn refill_instruction(&self, base_amount: u64, quote_amount: u64) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: self.deposit_accounts(),
+            data: openbook_twap::instruction::Refill {
+                base_amount,
+                quote_amount,
+            }
+            .data(),
+        }
+    }
+
+    fn place_order_accounts(&self, side: Side) -> Vec<AccountMeta> {
+        let (user_token_account, market_vault) = match side {
+            Side::Bid => (self.user.quote_account, self.market.market_quote_vault),
+            Side::Ask => (self.user.base_account, self.market.market_base_vault),
+        };
+
+        openbook_twap::accounts::PlaceOrder {
+            signer: self.user.owner.pubkey(),
+            open_orders_account: self.user.open_orders_account,
+            twap_market: self.market.twap_market,
+            user_token_account,
+            market: self.market.market.pubkey(),
+            bids: self.market.bids,
+            asks: self.market.asks,
+            event_heap: self.market.event_heap,
+            market_vault,
+            token_program: spl_token::ID,
+            openbook_program: openbook_v2::ID,
+        }
+        .to_account_metas(None)
+    }
+
+    pub fn place_order_instruction(&self, args: PlaceOrderArgs) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: self.place_order_accounts(args.side),
+            data: openbook_twap::instruction::PlaceOrder {
+                place_order_args: args,
+            }
+            .data(),
+        }
+    }
+
+    pub fn edit_order_instruction(
+        &self,
+        client_order_id: u64,
+        expected_cancel_size: i64,
+        place_order: PlaceOrderArgs,
+    ) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: self.place_order_accounts(place_order.side),
+            data: openbook_twap::instruction::EditOrder {
+                client_order_id,
+                expected_cancel_size,
+                place_order,
+            }
+            .data(),
+        }
+    }
+
+    pub fn cancel_order_instruction(&self, client_order_id: u64) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::CancelOrder {
+                signer: self.user.owner.pubkey(),
+                twap_market: self.market.twap_market,
+                open_orders_account: self.user.open_orders_account,
+                market: self.market.market.pubkey(),
+                bids: self.market.bids,
+                asks: self.market.asks,
+                openbook_program: openbook_v2::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::CancelOrderByClientId { client_order_id }.data(),
+        }
+    }
+
+    pub fn cancel_all_orders_instruction(&self, side_option: Option<Side>, limit: u8) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::CancelOrder {
+                signer: self.user.owner.pubkey(),
+                twap_market: self.market.twap_market,
+                open_orders_account: self.user.open_orders_account,
+                market: self.market.market.pubkey(),
+                bids: self.market.bids,
+                asks: self.market.asks,
+                openbook_program: openbook_v2::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::CancelAllOrders { side_option, limit }.data(),
+        }
+    }
+
+    pub fn cancel_and_place_orders_instruction(
+        &self,
+        cancel_client_orders_ids: Vec<u64>,
+        place_orders: Vec<PlaceOrderArgs>,
+    ) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::CancelAndPlaceOrders {
+                signer: self.user.owner.pubkey(),
+                twap_market: self.market.twap_market,
+                open_orders_account: self.user.open_orders_account,
+                user_quote_account: self.user.quote_account,
+                user_base_account: self.user.base_account,
+                market: self.market.market.pubkey(),
+                bids: self.market.bids,
+                asks: self.market.asks,
+                event_heap: self.market.event_heap,
+                market_quote_vault: self.market.market_quote_vault,
+                market_base_vault: self.market.market_base_vault,
+                token_program: spl_token::ID,
+                openbook_program: openbook_v2::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::CancelAndPlaceOrders {
+                cancel_client_orders_ids,
+                place_orders,
+            }
+            .data(),
+        }
+    }
+
+    pub fn place_take_order_instruction(&self, args: PlaceTakeOrderArgs) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::PlaceTakeOrder {
+                twap_market: self.market.twap_market,
+                signer: self.user.owner.pubkey(),
+                market: self.market.market.pubkey(),
+                market_authority: self.market.market_authority,
+                bids: self.market.bids,
+                asks: self.market.asks,
+                market_base_vault: self.market.market_base_vault,
+                market_quote_vault: self.market.market_quote_vault,
+                event_heap: self.market.event_heap,
+                user_base_account: self.user.base_account,
+                user_quote_account: self.user.quote_account,
+                token_program: spl_token::ID,
+                openbook_program: openbook_v2::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::PlaceTakeOrder { args }.data(),
+        }
+    }
+
+    pub fn crank_twap_instruction(&self) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::CrankTWAP {
+                twap_market: self.market.twap_market,
+                market: self.market.market.pubkey(),
+                bids: self.market.bids,
+                asks: self.market.asks,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::CrankTwap {}.data(),
+        }
+    }
+
+    pub fn consume_events_instruction(&self, limit: usize) -> Instruction {
+        let mut accounts = openbook_twap::accounts::ConsumeEvents {
+            twap_market: self.market.twap_market,
+            market: self.market.market.pubkey(),
+            bids: self.market.bids,
+            asks: self.market.asks,
+            event_heap: self.market.event_heap,
+            openbook_program: openbook_v2::ID,
+        }
+        .to_account_metas(None);
+        accounts.push(AccountMeta::new(self.user.open_orders_account, false));
+
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts,
+            data: openbook_twap::instruction::ConsumeEvents { limit }.data(),
+        }
+    }
+
+    pub fn settle_funds_instruction(&self) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::SettleFunds {
+                owner: self.user.owner.pubkey(),
+                open_orders_account: self.user.open_orders_account,
+                twap_market: self.market.twap_market,
+                market: self.market.market.pubkey(),
+                bids: None,
+                asks: None,
+                market_authority: self.market.market_authority,
+                market_base_vault: self.market.market_base_vault,
+                market_quote_vault: self.market.market_quote_vault,
+                user_base_account: self.user.base_account,
+                user_quote_account: self.user.quote_account,
+                token_program: spl_token::ID,
+                openbook_program: openbook_v2::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::SettleFunds {}.data(),
+        }
+    }
+
+    pub fn prune_orders_instruction(&self, limit: u8) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::PruneOrders {
+                twap_market: self.market.twap_market,
+                open_orders_account: self.user.open_orders_account,
+                market: self.market.market.pubkey(),
+                bids: self.market.bids,
+                asks: self.market.asks,
+                openbook_program: openbook_v2::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::PruneOrders { limit }.data(),
+        }
+    }
+
+    pub fn settle_funds_expired_instruction(&self) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::SettleFundsExpired {
+                twap_market: self.market.twap_market,
+                open_orders_account: self.user.open_orders_account,
+                market: self.market.market.pubkey(),
+                market_authority: self.market.market_authority,
+                market_base_vault: self.market.market_base_vault,
+                market_quote_vault: self.market.market_quote_vault,
+                user_base_account: self.user.base_account,
+                user_quote_account: self.user.quote_account,
+                token_program: spl_token::ID,
+                openbook_program: openbook_v2::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::SettleFundsExpired {}.data(),
+        }
+    }
+
+    pub fn close_open_orders_account_instruction(&self) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::CloseOpenOrdersAccount {
+                owner: self.user.owner.pubkey(),
+                open_orders_indexer: self.user.open_orders_indexer,
+                open_orders_account: self.user.open_orders_account,
+                sol_destination: self.user.owner.pubkey(),
+                twap_market: self.market.twap_market,
+                openbook_program: openbook_v2::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::CloseOpenOrdersAccount {}.data(),
+        }
+    }
+
+    pub fn close_market_instruction(&self) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::CloseMarket {
+                close_market_rent_receiver: self.context.payer.pubkey(),
+                close_authority: Some(self.context.payer.pubkey()),
+                twap_market: self.market.twap_market,
+                market: self.market.market.pubkey(),
+                bids: self.market.bids,
+                asks: self.market.asks,
+                event_heap: self.market.event_heap,
+                token_program: spl_token::ID,
+                openbook_program: openbook_v2::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::CloseMarket {}.data(),
+        }
+    }
+
+    pub fn set_close_authority_instruction(
+        &self,
+        close_authority: Pubkey,
+        new_close_authority: Option<Pubkey>,
+    ) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::SetCloseAuthority {
+                twap_market: self.market.twap_market,
+                close_authority,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::SetCloseAuthority {
+                new_close_authority,
+            }
+            .data(),
+        }
+    }
+
+    pub fn migrate_twap_market_instruction(&self, twap_market: Pubkey) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::MigrateTWAPMarket {
+                twap_market,
+                payer: self.context.payer.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::MigrateTwapMarket {}.data(),
+        }
+    }
+
+    /// The decision pair `authority` creates for these two TWAP markets
+    pub fn decision_pair(
+        authority: Pubkey,
+        pass_twap_market: Pubkey,
+        fail_twap_market: Pubkey,
+    ) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                b"decision_pair",
+                authority.as_ref(),
+                pass_twap_market.as_ref(),
+                fail_twap_market.as_ref(),
+            ],
+            &openbook_twap::ID,
+        )
+        .0
+    }
+
+    pub fn create_decision_pair_instruction(
+        &self,
+        authority: Pubkey,
+        pass_twap_market: Pubkey,
+        fail_twap_market: Pubkey,
+        start_slot: u64,
+        end_slot: u64,
+        pass_threshold_bps: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::CreateDecisionPair {
+                decision_pair: Self::decision_pair(authority, pass_twap_market, fail_twap_market),
+                pass_twap_market,
+                fail_twap_market,
+                authority,
+                payer: self.context.payer.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::CreateDecisionPair {
+                start_slot,
+                end_slot,
+                pass_threshold_bps,
+            }
+            .data(),
+        }
+    }
+
+    /// Resolves the decision pair the payer, the markets' close authority,
+    /// created for these two TWAP markets
+    pub fn resolve_decision_instruction(
+        &self,
+        pass_twap_market: Pubkey,
+        fail_twap_market: Pubkey,
+    ) -> Instruction {
+        let authority = self.context.payer.pubkey();
+
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::ResolveDecision {
+                decision_pair: Self::decision_pair(authority, pass_twap_market, fail_twap_market),
+                pass_twap_market,
+                fail_twap_market,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::ResolveDecision {}.data(),
+        }
+    }
+
+    pub fn close_decision_pair_instruction(
+        &self,
+        pass_twap_market: Pubkey,
+        fail_twap_market: Pubkey,
+    ) -> Instruction {
+        let authority = self.context.payer.pubkey();
+
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::CloseDecisionPair {
+                decision_pair: Self::decision_pair(authority, pass_twap_market, fail_twap_market),
+                authority,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::CloseDecisionPair {}.data(),
+        }
+    }
+
+    pub fn finalize_twap_instruction(&self) -> Instruction {
+        Instruction {
+            program_id: openbook_twap::ID,
+            accounts: openbook_twap::accounts::FinalizeTWAP {
+                twap_market: self.market.twap_market,
+            }
+            .to_account_metas(None),
+            data: openbook_twap::instruction::FinalizeTwap {}.data(),
+        }
+    }
+}
+
+/// Sends `instruction` in its own transaction, paid for by the context's
+/// payer and also signed by whichever of `signers` it requires, since a
+/// transaction can't carry signatures for keys it doesn't reference
+async fn send(
+    context: &mut ProgramTestContext,
+    instruction: Instruction,
+    signers: &[&Keypair],
+) -> std::result::Result<(), BanksClientError> {
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend(signers.iter().filter(|signer| {
+        signer.pubkey() != context.payer.pubkey()
+            && instruction
+                .accounts
+                .iter()
+                .any(|meta| meta.is_signer && meta.pubkey == signer.pubkey())
+    }));
+
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        context.last_blockhash,
+    );
+
+    context.banks_client.process_transaction(transaction).await
+}
+
+/// Derives a market's addresses and allocates its book and event heap
+fn add_market(program_test: &mut ProgramTest, base_mint: Pubkey, quote_mint: Pubkey) -> TestMarket {
+    let market = Keypair::new();
+    let (twap_market, _) = Pubkey::find_program_address(
+        &[b"twap_market", market.pubkey().as_ref()],
+        &openbook_twap::ID,
+    );
+    let (market_authority, _) =
+        Pubkey::find_program_address(&[b"Market", market.pubkey().as_ref()], &openbook_v2::ID);
+    let (event_authority, _) =
+        Pubkey::find_program_address(&[b"__event_authority"], &openbook_v2::ID);
+
+    // openbook expects the book and the event heap to be allocated, but
+    // they're too large to create through a CPI
+    let book_side_space = 8 + std::mem::size_of::<openbook_v2::state::BookSide>();
+    let event_heap_space = 8 + std::mem::size_of::<openbook_v2::state::EventHeap>();
+    let bids = Pubkey::new_unique();
+    let asks = Pubkey::new_unique();
+    let event_heap = Pubkey::new_unique();
+    add_account(
+        program_test,
+        bids,
+        vec![0; book_side_space],
+        openbook_v2::ID,
+        false,
+    );
+    add_account(
+        program_test,
+        asks,
+        vec![0; book_side_space],
+        openbook_v2::ID,
+        false,
+    );
+    add_account(
+        program_test,
+        event_heap,
+        vec![0; event_heap_space],
+        openbook_v2::ID,
+        false,
+    );
+
+    TestMarket {
+        twap_market,
+        market_authority,
+        event_authority,
+        bids,
+        asks,
+        event_heap,
+        base_mint,
+        quote_mint,
+        market_base_vault: get_associated_token_address(&market_authority, &base_mint),
+        market_quote_vault: get_associated_token_address(&market_authority, &quote_mint),
+        market,
+    }
+}
+
+fn add_account(
+    program_test: &mut ProgramTest,
+    address: Pubkey,
+    data: Vec<u8>,
+    owner: Pubkey,
+    executable: bool,
+) {
+    program_test.add_account(
+        address,
+        Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner,
+            executable,
+            rent_epoch: 0,
+        },
+    );
+}
+
+fn add_mint(program_test: &mut ProgramTest, mint_authority: Pubkey, decimals: u8) -> Pubkey {
+    let mint = Pubkey::new_unique();
+    let mut data = vec![0; spl_token::state::Mint::LEN];
+    spl_token::state::Mint {
+        mint_authority: COption::Some(mint_authority),
+        supply: u64::MAX / 2,
+        decimals,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    }
+    .pack_into_slice(&mut data);
+
+    add_account(program_test, mint, data, spl_token::ID, false);
+    mint
+}
+
+fn add_token_account(
+    program_test: &mut ProgramTest,
+    mint: Pubkey,
+    owner: Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let token_account = Pubkey::new_unique();
+    let mut data = vec![0; spl_token::state::Account::LEN];
+    spl_token::state::Account {
+        mint,
+        owner,
+        amount,
+        delegate: COption::None,
+        state: spl_token::state::AccountState::Initialized,
+        is_native: COption::None,
+        delegated_amount: 0,
+        close_authority: COption::None,
+    }
+    .pack_into_slice(&mut data);
+
+    add_account(program_test, token_account, data, spl_token::ID, false);
+    token_account
+}
@@ -0,0 +1,576 @@
+use anchor_lang::prelude::{Pubkey, Rent};
+use anchor_lang::{AnchorSerialize, Discriminator};
+use openbook_twap::{
+    CreateTWAPMarketArgs, DecisionOutcome, DecisionPair, OpenBookTWAPError, PlaceOrderType,
+    PlaceTakeOrderArgs, PostEndTrading, Side, TWAPMarket, TWAPMarketV0_2, TWAPOracleV0_2,
+    TWAPWeighting, TWAP_MARKET_SPACE, TWAP_MARKET_V0_2_SPACE, TWAP_MARKET_VERSION,
+};
+use program_test::*;
+use solana_sdk::signature::{Keypair, Signer};
+
+mod program_test;
+
+#[tokio::test]
+#[ignore = "requires tests/fixtures/openbook_v2.so"]
+async fn test_oracle_follows_the_book() {
+    let mut test = TestContext::start().await;
+    test.create_market(twap_market_args()).await;
+
+    let twap_market = test.twap_market().await.unwrap();
+    let start_slot = twap_market.twap_oracle.initial_slot;
+    assert_eq!(twap_market.market, test.market.market.pubkey());
+    assert_eq!(twap_market.twap_oracle.num_checkpoints, 1);
+    assert_eq!(
+        twap_market.twap_oracle.observation_aggregator,
+        EXPECTED_VALUE as u128
+    );
+
+    // orders placed in the slot the market was created in aren't observed
+    test.send(test.place_order_instruction(limit_order(Side::Bid, 490_000, 1)))
+        .await
+        .unwrap();
+    test.send(test.place_order_instruction(limit_order(Side::Ask, 510_000, 2)))
+        .await
+        .unwrap();
+    let oracle = test.twap_market().await.unwrap().twap_oracle;
+    assert_eq!(oracle.last_updated_slot, start_slot);
+
+    test.advance_slots(1).await;
+    test.send(test.crank_twap_instruction()).await.unwrap();
+
+    let oracle = test.twap_market().await.unwrap().twap_oracle;
+    assert_eq!(oracle.last_observation, 500_000);
+    assert_eq!(oracle.observation_aggregator, 1_000_000);
+
+    // an ask at $50 moves the midpoint to $49.5
+    test.send(test.place_order_instruction(limit_order(Side::Ask, 500_000, 3)))
+        .await
+        .unwrap();
+    test.advance_slots(1).await;
+    test.send(test.crank_twap_instruction()).await.unwrap();
+
+    let oracle = test.twap_market().await.unwrap().twap_oracle;
+    assert_eq!(oracle.last_observation, 495_000);
+    assert_eq!(oracle.observation_aggregator, 1_495_000);
+
+    // moving it to $50.5 puts the midpoint at $49.75
+    test.send(test.edit_order_instruction(3, 1, limit_order(Side::Ask, 505_000, 3)))
+        .await
+        .unwrap();
+    test.advance_slots(1).await;
+    test.send(test.crank_twap_instruction()).await.unwrap();
+
+    let oracle = test.twap_market().await.unwrap().twap_oracle;
+    assert_eq!(oracle.last_observation, 497_500);
+    assert_eq!(oracle.observation_aggregator, 1_992_500);
+
+    test.send(test.cancel_order_instruction(3)).await.unwrap();
+    test.advance_slots(1).await;
+    test.send(test.crank_twap_instruction()).await.unwrap();
+
+    let twap_market = test.twap_market().await.unwrap();
+    assert_eq!(twap_market.twap_oracle.last_observation, 500_000);
+    assert_eq!(twap_market.twap_oracle.observation_aggregator, 2_492_500);
+    // 2_492_500 / 5
+    assert_eq!(
+        twap_market.twap_since_start(start_slot + 4).unwrap(),
+        498_500
+    );
+    let checkpoint = twap_market.twap_oracle.live_checkpoint();
+
+    // a jump to $102.5 is clamped to $1 per update
+    test.send(test.cancel_and_place_orders_instruction(
+        vec![1, 2],
+        vec![
+            limit_order(Side::Bid, 1_000_000, 1),
+            limit_order(Side::Ask, 1_050_000, 2),
+        ],
+    ))
+    .await
+    .unwrap();
+    test.advance_slots(1).await;
+    test.send(test.crank_twap_instruction()).await.unwrap();
+
+    let oracle = test.twap_market().await.unwrap().twap_oracle;
+    assert_eq!(oracle.last_observation, 510_000);
+    assert_eq!(oracle.observation_aggregator, 3_002_500);
+
+    // taking observes the book before the take
+    test.advance_slots(1).await;
+    test.send(test.place_take_order_instruction(PlaceTakeOrderArgs {
+        side: Side::Bid,
+        price_lots: 1_050_000,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 1_050_000,
+        order_type: PlaceOrderType::ImmediateOrCancel,
+        limit: 255,
+    }))
+    .await
+    .unwrap();
+
+    let oracle = test.twap_market().await.unwrap().twap_oracle;
+    assert_eq!(oracle.last_observation, 520_000);
+    assert_eq!(oracle.observation_aggregator, 3_522_500);
+
+    // the take emptied the asks, so there's nothing to observe
+    test.advance_slots(1).await;
+    test.send(test.crank_twap_instruction()).await.unwrap();
+
+    let twap_market = test.twap_market().await.unwrap();
+    let oracle = &twap_market.twap_oracle;
+    assert_eq!(oracle.last_observed_slot, start_slot + 7);
+    assert_eq!(oracle.last_updated_slot, start_slot + 6);
+    assert_eq!(oracle.observation_aggregator, 3_522_500);
+    // the checkpoint interval hasn't passed since the market was created
+    assert_eq!(oracle.num_checkpoints, 1);
+
+    // (3_522_500 + 520_000) / 8, extrapolating the last observation
+    assert_eq!(
+        twap_market.twap_since_start(start_slot + 7).unwrap(),
+        505_312
+    );
+    // (3_522_500 - 2_492_500) / 2
+    assert_eq!(
+        TWAPMarket::twap_between(&checkpoint, &oracle.live_checkpoint()).unwrap(),
+        515_000
+    );
+
+    // other programs get the same TWAPs from `get_twap`
+    let twap_market = test.market.twap_market;
+    assert_eq!(
+        test.get_twap(twap_market, TWAPWeighting::Slot, None, None)
+            .await
+            .unwrap(),
+        505_312
+    );
+    // (3_522_500 + 520_000 - 500_000) / 7, without the starting observation
+    assert_eq!(
+        test.get_twap(
+            twap_market,
+            TWAPWeighting::Slot,
+            Some(start_slot),
+            Some(start_slot + 7)
+        )
+        .await
+        .unwrap(),
+        506_071
+    );
+    let error = test
+        .get_twap(
+            twap_market,
+            TWAPWeighting::Slot,
+            Some(start_slot),
+            Some(start_slot + 8),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::TWAPWindowInFuture))
+    );
+
+    // the maker's fill is settled once the event heap is consumed
+    test.send(test.consume_events_instruction(10))
+        .await
+        .unwrap();
+
+    let quote_account = test.user.quote_account;
+    let quote_before = test.token_balance(quote_account).await;
+    test.send(test.settle_funds_instruction()).await.unwrap();
+    let quote_after = test.token_balance(quote_account).await;
+    assert!(quote_after - quote_before >= 1_050_000 * QUOTE_LOT_SIZE as u64);
+
+    // once the market expires, the remaining bid can be pruned, the funds
+    // settled and everything closed
+    test.advance_slots(1).await;
+    let unix_timestamp = test.clock().await.unix_timestamp;
+    test.set_unix_timestamp(unix_timestamp + MARKET_DURATION_SECONDS + 1)
+        .await;
+
+    test.send(test.prune_orders_instruction(255)).await.unwrap();
+    test.send(test.settle_funds_expired_instruction())
+        .await
+        .unwrap();
+    test.send(test.close_open_orders_account_instruction())
+        .await
+        .unwrap();
+    test.send(test.close_market_instruction()).await.unwrap();
+
+    assert!(test.twap_market().await.is_none());
+
+    // the user traded with themselves without fees, so they end up with
+    // what they started with
+    assert_eq!(
+        test.token_balance(test.user.base_account).await,
+        BASE_AMOUNT
+    );
+    assert_eq!(test.token_balance(quote_account).await, QUOTE_AMOUNT);
+}
+
+#[tokio::test]
+#[ignore = "requires tests/fixtures/openbook_v2.so"]
+async fn test_trading_after_end_slot() {
+    let mut test = TestContext::start().await;
+    let slot = test.clock().await.slot;
+    test.create_market(CreateTWAPMarketArgs {
+        end_slot: Some(slot + 2),
+        post_end_trading: PostEndTrading::Reject,
+        ..twap_market_args()
+    })
+    .await;
+
+    test.send(test.place_order_instruction(limit_order(Side::Bid, 490_000, 1)))
+        .await
+        .unwrap();
+    test.send(test.place_order_instruction(limit_order(Side::Ask, 510_000, 2)))
+        .await
+        .unwrap();
+
+    test.advance_slots(2).await;
+    test.send(test.crank_twap_instruction()).await.unwrap();
+
+    let oracle = test.twap_market().await.unwrap().twap_oracle;
+    assert_eq!(oracle.observation_aggregator, 1_500_000);
+
+    test.advance_slots(1).await;
+
+    let error = test
+        .send(test.place_order_instruction(limit_order(Side::Bid, 495_000, 3)))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::TWAPMarketEnded))
+    );
+
+    // the book stays open for cancelling
+    test.send(test.cancel_order_instruction(1)).await.unwrap();
+
+    // the market can't be closed before its TWAP is recorded
+    let error = test
+        .send(test.close_market_instruction())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::TWAPNotFinalized))
+    );
+
+    test.send(test.finalize_twap_instruction()).await.unwrap();
+    let oracle = test.twap_market().await.unwrap().twap_oracle;
+    // 1_500_000 / 3
+    assert_eq!(oracle.final_twap, Some(500_000));
+
+    test.advance_slots(1).await;
+    let error = test
+        .send(test.finalize_twap_instruction())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::TWAPAlreadyFinalized))
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires tests/fixtures/openbook_v2.so"]
+async fn test_cancel_all_orders_and_refill() {
+    let mut test = TestContext::start().await;
+    test.create_market(twap_market_args()).await;
+    let start_slot = test.twap_market().await.unwrap().twap_oracle.initial_slot;
+
+    test.send(test.place_order_instruction(limit_order(Side::Bid, 490_000, 1)))
+        .await
+        .unwrap();
+    test.send(test.place_order_instruction(limit_order(Side::Ask, 510_000, 2)))
+        .await
+        .unwrap();
+    test.send(test.place_order_instruction(limit_order(Side::Bid, 480_000, 3)))
+        .await
+        .unwrap();
+
+    // cancelling observes the book before the orders are pulled
+    test.advance_slots(1).await;
+    test.send(test.cancel_all_orders_instruction(Some(Side::Bid), 255))
+        .await
+        .unwrap();
+
+    let oracle = test.twap_market().await.unwrap().twap_oracle;
+    assert_eq!(oracle.last_updated_slot, start_slot + 1);
+    assert_eq!(oracle.last_observation, 500_000);
+
+    // only the ask is left, so there's nothing to observe
+    test.advance_slots(1).await;
+    test.send(test.crank_twap_instruction()).await.unwrap();
+
+    let oracle = test.twap_market().await.unwrap().twap_oracle;
+    assert_eq!(oracle.last_observed_slot, start_slot + 2);
+    assert_eq!(oracle.last_updated_slot, start_slot + 1);
+
+    test.send(test.cancel_all_orders_instruction(None, 255))
+        .await
+        .unwrap();
+
+    // half of the balance was deposited and is free again, so refilling to
+    // three quarters of it only moves another quarter
+    test.send(test.refill_instruction(BASE_AMOUNT * 3 / 4, QUOTE_AMOUNT * 3 / 4))
+        .await
+        .unwrap();
+    assert_eq!(
+        test.token_balance(test.user.base_account).await,
+        BASE_AMOUNT / 4
+    );
+    assert_eq!(
+        test.token_balance(test.user.quote_account).await,
+        QUOTE_AMOUNT / 4
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires tests/fixtures/openbook_v2.so"]
+async fn test_set_close_authority() {
+    let mut test = TestContext::start().await;
+    test.create_market(twap_market_args()).await;
+
+    let payer = test.context.payer.pubkey();
+    assert_eq!(
+        test.twap_market().await.unwrap().close_authority,
+        Some(payer)
+    );
+
+    let new_close_authority = Keypair::new();
+    test.send(test.set_close_authority_instruction(payer, Some(new_close_authority.pubkey())))
+        .await
+        .unwrap();
+
+    // the payer can neither take it back nor close the market
+    let error = test
+        .send(test.set_close_authority_instruction(payer, Some(payer)))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::InvalidCloseAuthority))
+    );
+    let error = test
+        .send(test.close_market_instruction())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::InvalidCloseAuthority))
+    );
+
+    test.send_signed(
+        test.set_close_authority_instruction(new_close_authority.pubkey(), None),
+        &[&new_close_authority],
+    )
+    .await
+    .unwrap();
+    assert_eq!(test.twap_market().await.unwrap().close_authority, None);
+}
+
+#[tokio::test]
+#[ignore = "requires tests/fixtures/openbook_v2.so"]
+async fn test_migrate_twap_market() {
+    let mut test = TestContext::start().await;
+
+    let market = Pubkey::new_unique();
+    let twap_market = Pubkey::new_unique();
+    let legacy = TWAPMarketV0_2 {
+        market,
+        pda_bump: 254,
+        twap_oracle: TWAPOracleV0_2 {
+            expected_value: EXPECTED_VALUE,
+            initial_slot: 100,
+            last_updated_slot: 150,
+            last_observed_slot: 160,
+            last_observation: 510_000,
+            observation_aggregator: 25_500_000,
+            max_observation_change_per_update_lots: MAX_UPDATE_LOTS,
+        },
+        close_market_rent_receiver: test.context.payer.pubkey(),
+    };
+    let mut data = TWAPMarket::DISCRIMINATOR.to_vec();
+    legacy.serialize(&mut data).unwrap();
+    data.resize(TWAP_MARKET_V0_2_SPACE, 0);
+    test.set_program_account(twap_market, data);
+
+    test.send(test.migrate_twap_market_instruction(twap_market))
+        .await
+        .unwrap();
+
+    let account = test
+        .context
+        .banks_client
+        .get_account(twap_market)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.data.len(), TWAP_MARKET_SPACE);
+    assert!(account.lamports >= Rent::default().minimum_balance(TWAP_MARKET_SPACE));
+
+    let migrated: TWAPMarket = test.account(twap_market).await.unwrap();
+    assert_eq!(migrated.version, TWAP_MARKET_VERSION);
+    assert_eq!(migrated.market, market);
+    assert_eq!(migrated.close_authority, None);
+    assert_eq!(migrated.twap_oracle.observation_aggregator, 25_500_000);
+    // 25_500_000 / (150 - 100 + 1)
+    assert_eq!(migrated.twap_since_start(150).unwrap(), 500_000);
+
+    test.advance_slots(1).await;
+    let error = test
+        .send(test.migrate_twap_market_instruction(twap_market))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::TWAPMarketAlreadyMigrated))
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires tests/fixtures/openbook_v2.so"]
+async fn test_decision() {
+    let mut test = TestContext::start().await;
+    let start_slot = test.clock().await.slot;
+    let end_slot = start_slot + 12;
+    let args = CreateTWAPMarketArgs {
+        end_slot: Some(end_slot),
+        ..twap_market_args()
+    };
+    test.create_market(args).await;
+    test.create_second_market(args).await;
+
+    let pass = test.market.twap_market;
+    let fail = test.second_market.twap_market;
+
+    // the first market is quoted around $60, while the second one is never
+    // quoted and stays at the expected value
+    test.send(test.place_order_instruction(limit_order(Side::Bid, 590_000, 1)))
+        .await
+        .unwrap();
+    test.send(test.place_order_instruction(limit_order(Side::Ask, 610_000, 2)))
+        .await
+        .unwrap();
+
+    // only the markets' close authority can create decisions for them, since
+    // decisions keep the markets from being closed
+    let payer = test.context.payer.pubkey();
+    let owner = test.user.owner.pubkey();
+    let error = test
+        .send(test.create_decision_pair_instruction(owner, pass, fail, start_slot, end_slot, 100))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::InvalidCloseAuthority))
+    );
+
+    let error = test
+        .send(test.create_decision_pair_instruction(payer, pass, fail, start_slot, end_slot, 10_001))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::InvalidPassThreshold))
+    );
+    let error = test
+        .send(test.create_decision_pair_instruction(payer, pass, fail, start_slot, end_slot + 1, 100))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::InvalidDecisionWindow))
+    );
+
+    // one pair each way round, so that one passes and the other fails
+    test.send(test.create_decision_pair_instruction(payer, pass, fail, start_slot, end_slot, 100))
+        .await
+        .unwrap();
+    test.send(test.create_decision_pair_instruction(payer, fail, pass, start_slot, end_slot, 100))
+        .await
+        .unwrap();
+    assert_eq!(test.twap_market().await.unwrap().unresolved_decisions, 2);
+
+    let error = test
+        .send(test.close_decision_pair_instruction(pass, fail))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::DecisionNotResolved))
+    );
+
+    let error = test
+        .send(test.resolve_decision_instruction(pass, fail))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::DecisionWindowNotOver))
+    );
+
+    for _ in 0..12 {
+        test.advance_slots(1).await;
+        test.send(test.crank_twap_instruction()).await.unwrap();
+    }
+    test.advance_slots(1).await;
+
+    // the market can't be closed while a decision depends on it
+    test.send(test.finalize_twap_instruction()).await.unwrap();
+    let error = test
+        .send(test.close_market_instruction())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::UnresolvedDecisions))
+    );
+
+    test.send(test.resolve_decision_instruction(pass, fail))
+        .await
+        .unwrap();
+    test.send(test.resolve_decision_instruction(fail, pass))
+        .await
+        .unwrap();
+
+    let decision_pair: DecisionPair = test
+        .account(TestContext::decision_pair(payer, pass, fail))
+        .await
+        .unwrap();
+    assert_eq!(decision_pair.authority, payer);
+    // (510_000 + 520_000 + ... + 600_000 + 2 * 600_000) / 12
+    assert_eq!(decision_pair.pass_twap, Some(562_500));
+    assert_eq!(decision_pair.fail_twap, Some(500_000));
+    assert_eq!(decision_pair.outcome, DecisionOutcome::Pass);
+
+    let decision_pair: DecisionPair = test
+        .account(TestContext::decision_pair(payer, fail, pass))
+        .await
+        .unwrap();
+    assert_eq!(decision_pair.outcome, DecisionOutcome::Fail);
+
+    assert_eq!(test.twap_market().await.unwrap().unresolved_decisions, 0);
+    let second_twap_market: TWAPMarket = test.account(fail).await.unwrap();
+    assert_eq!(second_twap_market.unresolved_decisions, 0);
+
+    test.advance_slots(1).await;
+    let error = test
+        .send(test.resolve_decision_instruction(pass, fail))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error_code(error),
+        Some(anchor_error(OpenBookTWAPError::DecisionAlreadyResolved))
+    );
+
+    // resolved pairs can be closed for their rent
+    test.send(test.close_decision_pair_instruction(pass, fail))
+        .await
+        .unwrap();
+    assert!(test
+        .account::<DecisionPair>(TestContext::decision_pair(payer, pass, fail))
+        .await
+        .is_none());
+}
@@ -13,6 +13,7 @@ import {
 } from "@openbook-dex/openbook-v2";
 
 import { expect, assert } from "chai";
+import { getAssociatedTokenAddressSync } from "@solana/spl-token";
 
 const { PublicKey, Keypair, SystemProgram } = anchor.web3;
 const { BN } = anchor;
@@ -39,6 +40,23 @@ const USDC_AMOUNT = 1000;
 
 const EXPECTED_VALUE = 50 * 10_000;
 const MAX_UPDATE_LOTS = 1 * 10_000;
+const MAX_SPREAD_BPS = 2_000;
+const CHECKPOINT_INTERVAL_SLOTS = 150;
+
+const CREATE_TWAP_MARKET_ARGS = {
+  expectedValue: new BN(EXPECTED_VALUE),
+  maxObservationChangePerUpdateLots: new BN(MAX_UPDATE_LOTS),
+  maxObservationChangePerUpdateBps: new BN(0),
+  observationChangeMode: { lots: {} },
+  maxObservationChangeScalingSlots: new BN(0),
+  maxSpreadBps: new BN(MAX_SPREAD_BPS),
+  wideSpreadBehavior: { skip: {} },
+  checkpointIntervalSlots: new BN(CHECKPOINT_INTERVAL_SLOTS),
+  accumulateLogObservations: true,
+  startDelaySlots: new BN(0),
+  endSlot: null,
+  postEndTrading: { continue: {} },
+};
 
 const META_DECIMALS = 9;
 const USDC_DECIMALS = 6;
@@ -82,6 +100,73 @@ describe("openbook-twap", () => {
     openbook = new OpenBookV2Client(provider);
   });
 
+  async function createTwapMarket(
+    base,
+    quote,
+    args: typeof CREATE_TWAP_MARKET_ARGS = CREATE_TWAP_MARKET_ARGS
+  ) {
+    let marketKP = Keypair.generate();
+
+    let [twapMarket] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("twap_market"),
+        marketKP.publicKey.toBuffer(),
+      ],
+      openbookTwap.programId
+    );
+
+    let [createMarketIxs, createMarketSigners] = await openbook.createMarketIx(
+      payer.publicKey,
+      "META/USDC",
+      quote,
+      base,
+      new BN(QUOTE_LOT_SIZE),
+      new BN(BASE_LOT_SIZE),
+      new BN(0),
+      new BN(0),
+      new BN(0),
+      null,
+      null,
+      twapMarket,
+      null,
+      twapMarket,
+      { confFilter: 0.1, maxStalenessSlots: 100 },
+      marketKP,
+      payer.publicKey
+    );
+
+    let tx = new anchor.web3.Transaction().add(...createMarketIxs);
+    [tx.recentBlockhash] = await banksClient.getLatestBlockhash();
+    tx.feePayer = payer.publicKey;
+    await provider.sendAndConfirm(tx, createMarketSigners);
+
+    await openbookTwap.methods
+      .createTwapMarket(args)
+      .accounts({
+        market: marketKP.publicKey,
+        twapMarket,
+      })
+      .rpc();
+
+    return {
+      market: marketKP.publicKey,
+      twapMarket,
+      storedMarket: await openbook.deserializeMarketAccount(
+        marketKP.publicKey
+      ),
+    };
+  }
+
+  async function expectError(promise: Promise<any>, code: string) {
+    try {
+      await promise;
+    } catch (error) {
+      assert.strictEqual(error.error?.errorCode?.code, code);
+      return;
+    }
+    assert.fail(`Expected a ${code} error`);
+  }
+
   it("Is initialized!", async () => {
     let mintAuthority = Keypair.generate();
     let META = await createMint(
@@ -173,7 +258,7 @@ describe("openbook-twap", () => {
     await provider.sendAndConfirm(tx, createMarketSigners);
 
     await openbookTwap.methods
-      .createTwapMarket(new BN(EXPECTED_VALUE), new BN(MAX_UPDATE_LOTS))
+      .createTwapMarket(CREATE_TWAP_MARKET_ARGS)
       .accounts({
         market: marketKP.publicKey,
         twapMarket,
@@ -185,6 +270,13 @@ describe("openbook-twap", () => {
     );
 
     assert.ok(storedTwapMarket.market.equals(market));
+    assert(storedTwapMarket.twapOracle.numCheckpoints.eqn(1));
+    assert(storedTwapMarket.twapOracle.maxSpreadBps.eqn(MAX_SPREAD_BPS));
+    assert(
+      storedTwapMarket.twapOracle.checkpoints[0].slot.eq(
+        storedTwapMarket.twapOracle.initialSlot
+      )
+    );
 
     let storedMarket = await openbook.deserializeMarketAccount(market);
 
@@ -399,6 +491,49 @@ describe("openbook-twap", () => {
     assert(TWAP / 10_000 > 50.416);
     assert(TWAP / 10_000 < 50.417);
 
+    // fewer than `CHECKPOINT_INTERVAL_SLOTS` slots have passed, so the only
+    // stored checkpoint is the one written when the market was created
+    let oracle = storedTwapMarket.twapOracle;
+    assert(oracle.numCheckpoints.eqn(1));
+    let initialCheckpoint = oracle.checkpoints[0];
+    assert(initialCheckpoint.slot.eq(oracle.initialSlot));
+    assert(initialCheckpoint.observationAggregator.eqn(EXPECTED_VALUE));
+    assert(oracle.logObservationAggregator.gtn(0));
+
+    // the on-chain view should agree with both computations
+    let viewedTWAP = await openbookTwap.methods
+      .getTwap({ slot: {} }, null, null)
+      .accounts({ twapMarket })
+      .view();
+    assert(viewedTWAP.eqn(TWAP));
+
+    // `advanceSlots` doesn't move the clock's unix timestamp, so the
+    // time-weighted TWAP only includes the expected value
+    assert(oracle.timeWeightedAggregator.eqn(EXPECTED_VALUE));
+    viewedTWAP = await openbookTwap.methods
+      .getTwap({ time: {} }, null, null)
+      .accounts({ twapMarket })
+      .view();
+    assert(viewedTWAP.eqn(EXPECTED_VALUE));
+
+    // windows may end at the live state rather than a stored checkpoint,
+    // here (302.5 - 50) / 5 = 50.5
+    viewedTWAP = await openbookTwap.methods
+      .getTwap({ slot: {} }, initialCheckpoint.slot, oracle.lastUpdatedSlot)
+      .accounts({ twapMarket })
+      .view();
+    assert(viewedTWAP.eqn(505 * 1_000));
+
+    try {
+      await openbookTwap.methods
+        .getTwap({ slot: {} }, oracle.lastUpdatedSlot, oracle.lastUpdatedSlot)
+        .accounts({ twapMarket })
+        .view();
+      assert.fail("Expected an EmptyTwapWindow error");
+    } catch (error) {
+      assert.include(error.toString(), "EmptyTWAPWindow");
+    }
+
     await cancelOrderByClientId(1);
     await cancelOrderByClientId(2);
 
@@ -425,6 +560,41 @@ describe("openbook-twap", () => {
     storedTwapMarket = await openbookTwap.account.twapMarket.fetch(twapMarket);
     assert(storedTwapMarket.twapOracle.lastObservation.eqn(50 * 10_000));
 
+    // the oracle can also be cranked without touching the book
+    await advanceSlots(1);
+    await openbookTwap.methods
+      .crankTwap()
+      .accounts({
+        twapMarket,
+        market,
+        bids: storedMarket.bids,
+        asks: storedMarket.asks,
+      })
+      .rpc();
+
+    storedTwapMarket = await openbookTwap.account.twapMarket.fetch(twapMarket);
+    assert(storedTwapMarket.twapOracle.lastObservation.eqn(49 * 10_000));
+
+    // cranking the event heap through the TWAP market also updates the
+    // oracle, although this slot has already been observed
+    await openbookTwap.methods
+      .consumeEvents(new BN(10))
+      .accounts({
+        twapMarket,
+        market,
+        bids: storedMarket.bids,
+        asks: storedMarket.asks,
+        eventHeap: storedMarket.eventHeap,
+        openbookProgram: OPENBOOK_PROGRAM_ID,
+      })
+      .remainingAccounts(
+        oos.map((pubkey) => ({ pubkey, isSigner: false, isWritable: true }))
+      )
+      .rpc();
+
+    storedTwapMarket = await openbookTwap.account.twapMarket.fetch(twapMarket);
+    assert(storedTwapMarket.twapOracle.lastObservation.eqn(49 * 10_000));
+
     let currentClock = await context.banksClient.getClock();
     let jumpAheadSlots = BigInt(elevenDaysInSeconds * 2.5);
     const newSlot = currentClock.slot + jumpAheadSlots;
@@ -480,6 +650,7 @@ describe("openbook-twap", () => {
         .closeMarket()
         .accounts({
           closeMarketRentReceiver: Keypair.generate().publicKey,
+          closeAuthority: provider.publicKey,
           twapMarket,
           market,
           bids: storedMarket.bids,
@@ -501,10 +672,28 @@ describe("openbook-twap", () => {
       }
     }
 
+    await expectError(
+      openbookTwap.methods
+        .closeMarket()
+        .accounts({
+          closeMarketRentReceiver: provider.publicKey,
+          closeAuthority: null,
+          twapMarket,
+          market,
+          bids: storedMarket.bids,
+          asks: storedMarket.asks,
+          eventHeap: storedMarket.eventHeap,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+        })
+        .rpc(),
+      "InvalidCloseAuthority"
+    );
+
     await openbookTwap.methods
       .closeMarket()
       .accounts({
         closeMarketRentReceiver: provider.publicKey,
+        closeAuthority: provider.publicKey,
         twapMarket,
         market,
         bids: storedMarket.bids,
@@ -527,5 +716,871 @@ describe("openbook-twap", () => {
       balanceDifference / 1e9,
       "SOL after closing the market"
     );
+
+    assert.isNull(
+      await banksClient.getAccount(twapMarket),
+      "The TWAP market should be closed along with the market"
+    );
+  });
+
+  it("Creates a market and its TWAP market in one instruction", async () => {
+    let mintAuthority = Keypair.generate();
+    let META = await createMint(
+      banksClient,
+      payer,
+      mintAuthority.publicKey,
+      null,
+      META_DECIMALS
+    );
+    let USDC = await createMint(
+      banksClient,
+      payer,
+      mintAuthority.publicKey,
+      null,
+      USDC_DECIMALS
+    );
+
+    const marketKP = Keypair.generate();
+    const [twapMarket] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("twap_market"),
+        marketKP.publicKey.toBuffer(),
+      ],
+      openbookTwap.programId
+    );
+    const [marketAuthority] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("Market"), marketKP.publicKey.toBuffer()],
+      OPENBOOK_PROGRAM_ID
+    );
+    const [eventAuthority] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("__event_authority")],
+      OPENBOOK_PROGRAM_ID
+    );
+
+    // openbook expects the book and the event heap to already be allocated
+    const bids = Keypair.generate();
+    const asks = Keypair.generate();
+    const eventHeap = Keypair.generate();
+    const rent = await banksClient.getRent();
+    const allocate = (account, space) =>
+      SystemProgram.createAccount({
+        fromPubkey: payer.publicKey,
+        newAccountPubkey: account.publicKey,
+        lamports: Number(rent.minimumBalance(BigInt(space))),
+        space,
+        programId: OPENBOOK_PROGRAM_ID,
+      });
+
+    let tx = new anchor.web3.Transaction().add(
+      allocate(bids, openbook.program.account.bookSide.size),
+      allocate(asks, openbook.program.account.bookSide.size),
+      allocate(eventHeap, openbook.program.account.eventHeap.size)
+    );
+    [tx.recentBlockhash] = await banksClient.getLatestBlockhash();
+    tx.feePayer = payer.publicKey;
+    await provider.sendAndConfirm(tx, [bids, asks, eventHeap]);
+
+    await openbookTwap.methods
+      .createMarketWithTwap(
+        "META/USDC",
+        new BN(QUOTE_LOT_SIZE),
+        new BN(BASE_LOT_SIZE),
+        new BN(0),
+        CREATE_TWAP_MARKET_ARGS
+      )
+      .accounts({
+        market: marketKP.publicKey,
+        twapMarket,
+        marketAuthority,
+        bids: bids.publicKey,
+        asks: asks.publicKey,
+        eventHeap: eventHeap.publicKey,
+        marketBaseVault: getAssociatedTokenAddressSync(
+          META,
+          marketAuthority,
+          true
+        ),
+        marketQuoteVault: getAssociatedTokenAddressSync(
+          USDC,
+          marketAuthority,
+          true
+        ),
+        baseMint: META,
+        quoteMint: USDC,
+        eventAuthority,
+        payer: payer.publicKey,
+        openbookProgram: OPENBOOK_PROGRAM_ID,
+      })
+      .signers([marketKP])
+      .rpc();
+
+    const storedMarket = await openbook.deserializeMarketAccount(
+      marketKP.publicKey
+    );
+    assert.ok(storedMarket.openOrdersAdmin.key.equals(twapMarket));
+    assert.ok(storedMarket.closeMarketAdmin.key.equals(twapMarket));
+    assert.ok(storedMarket.consumeEventsAdmin.key.equals(PublicKey.default));
+    assert.strictEqual(storedMarket.makerFee.toNumber(), 0);
+    assert.strictEqual(storedMarket.takerFee.toNumber(), 0);
+
+    const storedTwapMarket = await openbookTwap.account.twapMarket.fetch(
+      twapMarket
+    );
+    assert.ok(storedTwapMarket.market.equals(marketKP.publicKey));
+    assert.strictEqual(
+      storedTwapMarket.twapOracle.expectedValue.toNumber(),
+      EXPECTED_VALUE
+    );
+  });
+
+  it("Manages open orders accounts and funds", async () => {
+    let mintAuthority = Keypair.generate();
+    let META = await createMint(
+      banksClient,
+      payer,
+      mintAuthority.publicKey,
+      null,
+      META_DECIMALS
+    );
+    let USDC = await createMint(
+      banksClient,
+      payer,
+      mintAuthority.publicKey,
+      null,
+      USDC_DECIMALS
+    );
+
+    let usdcAccount = await createAccount(
+      banksClient,
+      payer,
+      USDC,
+      payer.publicKey
+    );
+    let metaAccount = await createAccount(
+      banksClient,
+      payer,
+      META,
+      payer.publicKey
+    );
+    await mintTo(
+      banksClient,
+      payer,
+      META,
+      metaAccount,
+      mintAuthority,
+      META_AMOUNT_SCALED
+    );
+    await mintTo(
+      banksClient,
+      payer,
+      USDC,
+      usdcAccount,
+      mintAuthority,
+      USDC_AMOUNT_SCALED
+    );
+
+    const { market, twapMarket, storedMarket } = await createTwapMarket(
+      META,
+      USDC
+    );
+
+    const [openOrdersIndexer] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("OpenOrdersIndexer"),
+        payer.publicKey.toBuffer(),
+      ],
+      OPENBOOK_PROGRAM_ID
+    );
+    const indexer =
+      await openbook.program.account.openOrdersIndexer.fetchNullable(
+        openOrdersIndexer
+      );
+    const accountNum = (indexer?.createdCounter ?? 0) + 1;
+
+    const [openOrdersAccount] = PublicKey.findProgramAddressSync(
+      [
+        anchor.utils.bytes.utf8.encode("OpenOrders"),
+        payer.publicKey.toBuffer(),
+        new BN(accountNum).toArrayLike(Buffer, "le", 4),
+      ],
+      OPENBOOK_PROGRAM_ID
+    );
+
+    const createOpenOrdersAccount = (openOrdersAccount) =>
+      openbookTwap.methods
+        .createOpenOrdersAccount("oo")
+        .accounts({
+          payer: payer.publicKey,
+          owner: payer.publicKey,
+          openOrdersIndexer,
+          openOrdersAccount,
+          twapMarket,
+          market,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+        })
+        .rpc();
+
+    // only the address openbook derives for the owner's next account works
+    await expectError(
+      createOpenOrdersAccount(Keypair.generate().publicKey),
+      "InvalidOpenOrdersAccount"
+    );
+
+    await createOpenOrdersAccount(openOrdersAccount);
+
+    const storedOpenOrders = await openbook.deserializeOpenOrderAccount(
+      openOrdersAccount
+    );
+    assert.ok(storedOpenOrders.market.equals(market));
+    assert.ok(storedOpenOrders.owner.equals(payer.publicKey));
+
+    const depositAccounts = {
+      owner: payer.publicKey,
+      userBaseAccount: metaAccount,
+      userQuoteAccount: usdcAccount,
+      openOrdersAccount,
+      twapMarket,
+      market,
+      marketBaseVault: storedMarket.marketBaseVault,
+      marketQuoteVault: storedMarket.marketQuoteVault,
+      openbookProgram: OPENBOOK_PROGRAM_ID,
+    };
+
+    // passing the book records an observation, but is optional
+    await openbookTwap.methods
+      .deposit(new BN(META_AMOUNT_SCALED / 2), new BN(USDC_AMOUNT_SCALED / 2))
+      .accounts({
+        ...depositAccounts,
+        bids: storedMarket.bids,
+        asks: storedMarket.asks,
+      })
+      .rpc();
+    // refill tops the open orders account up to the given amounts
+    await openbookTwap.methods
+      .refill(new BN(META_AMOUNT_SCALED), new BN(USDC_AMOUNT_SCALED))
+      .accounts({ ...depositAccounts, bids: null, asks: null })
+      .rpc();
+
+    assert.strictEqual(
+      Number((await getAccount(banksClient, metaAccount)).amount),
+      0
+    );
+
+    await openbookTwap.methods
+      .settleFunds()
+      .accounts({
+        owner: payer.publicKey,
+        openOrdersAccount,
+        twapMarket,
+        market,
+        bids: null,
+        asks: null,
+        marketAuthority: storedMarket.marketAuthority,
+        marketBaseVault: storedMarket.marketBaseVault,
+        marketQuoteVault: storedMarket.marketQuoteVault,
+        userBaseAccount: metaAccount,
+        userQuoteAccount: usdcAccount,
+        openbookProgram: OPENBOOK_PROGRAM_ID,
+      })
+      .rpc();
+
+    assert.strictEqual(
+      Number((await getAccount(banksClient, metaAccount)).amount),
+      META_AMOUNT_SCALED
+    );
+
+    await openbookTwap.methods
+      .closeOpenOrdersAccount()
+      .accounts({
+        owner: payer.publicKey,
+        openOrdersIndexer,
+        openOrdersAccount,
+        solDestination: payer.publicKey,
+        twapMarket,
+        openbookProgram: OPENBOOK_PROGRAM_ID,
+      })
+      .rpc();
+
+    assert.isNull(await banksClient.getAccount(openOrdersAccount));
+  });
+
+  it("Rejects accounts that don't belong to the TWAP market", async () => {
+    let mintAuthority = Keypair.generate();
+    let META = await createMint(
+      banksClient,
+      payer,
+      mintAuthority.publicKey,
+      null,
+      META_DECIMALS
+    );
+    let USDC = await createMint(
+      banksClient,
+      payer,
+      mintAuthority.publicKey,
+      null,
+      USDC_DECIMALS
+    );
+    let usdcAccount = await createAccount(
+      banksClient,
+      payer,
+      USDC,
+      payer.publicKey
+    );
+    let metaAccount = await createAccount(
+      banksClient,
+      payer,
+      META,
+      payer.publicKey
+    );
+
+    const a = await createTwapMarket(META, USDC);
+    const b = await createTwapMarket(META, USDC);
+
+    const openOrdersAccount = Keypair.generate().publicKey;
+
+    const placeOrderArgs = {
+      side: Side.Bid,
+      priceLots: new BN(50 * 10_000),
+      maxBaseLots: new BN(1),
+      maxQuoteLotsIncludingFees: new BN(50 * 10_000),
+      clientOrderId: new BN(1),
+      orderType: OrderType.Limit,
+      expiryTimestamp: new BN(0),
+      selfTradeBehavior: SelfTradeBehavior.DecrementTake,
+      limit: 255,
+    };
+
+    // each builder takes the `twapMarket`, `market`, `bids` and `asks` to
+    // use and fills in every other account from market `a`
+    const builders = {
+      placeOrder: (accounts) =>
+        openbookTwap.methods.placeOrder(placeOrderArgs).accounts({
+          signer: payer.publicKey,
+          openOrdersAccount,
+          userTokenAccount: usdcAccount,
+          eventHeap: a.storedMarket.eventHeap,
+          marketVault: a.storedMarket.marketQuoteVault,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+          ...accounts,
+        }),
+      editOrder: (accounts) =>
+        openbookTwap.methods
+          .editOrder(new BN(1), new BN(1), placeOrderArgs)
+          .accounts({
+            signer: payer.publicKey,
+            openOrdersAccount,
+            userTokenAccount: usdcAccount,
+            eventHeap: a.storedMarket.eventHeap,
+            marketVault: a.storedMarket.marketQuoteVault,
+            openbookProgram: OPENBOOK_PROGRAM_ID,
+            ...accounts,
+          }),
+      cancelOrderByClientId: (accounts) =>
+        openbookTwap.methods.cancelOrderByClientId(new BN(1)).accounts({
+          signer: payer.publicKey,
+          openOrdersAccount,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+          ...accounts,
+        }),
+      cancelAllOrders: (accounts) =>
+        openbookTwap.methods.cancelAllOrders(null, 255).accounts({
+          signer: payer.publicKey,
+          openOrdersAccount,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+          ...accounts,
+        }),
+      pruneOrders: (accounts) =>
+        openbookTwap.methods.pruneOrders(100).accounts({
+          openOrdersAccount,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+          ...accounts,
+        }),
+      closeMarket: (accounts) =>
+        openbookTwap.methods.closeMarket().accounts({
+          closeMarketRentReceiver: payer.publicKey,
+          closeAuthority: payer.publicKey,
+          eventHeap: a.storedMarket.eventHeap,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+          ...accounts,
+        }),
+      placeTakeOrder: (accounts) =>
+        openbookTwap.methods
+          .placeTakeOrder({
+            side: Side.Bid,
+            priceLots: new BN(50 * 10_000),
+            maxBaseLots: new BN(1),
+            maxQuoteLotsIncludingFees: new BN(50 * 10_000),
+            orderType: OrderType.ImmediateOrCancel,
+            limit: 255,
+          })
+          .accounts({
+            signer: payer.publicKey,
+            marketAuthority: a.storedMarket.marketAuthority,
+            marketBaseVault: a.storedMarket.marketBaseVault,
+            marketQuoteVault: a.storedMarket.marketQuoteVault,
+            eventHeap: a.storedMarket.eventHeap,
+            userBaseAccount: metaAccount,
+            userQuoteAccount: usdcAccount,
+            openbookProgram: OPENBOOK_PROGRAM_ID,
+            ...accounts,
+          }),
+      cancelAndPlaceOrders: (accounts) =>
+        openbookTwap.methods.cancelAndPlaceOrders([], []).accounts({
+          signer: payer.publicKey,
+          openOrdersAccount,
+          userQuoteAccount: usdcAccount,
+          userBaseAccount: metaAccount,
+          eventHeap: a.storedMarket.eventHeap,
+          marketQuoteVault: a.storedMarket.marketQuoteVault,
+          marketBaseVault: a.storedMarket.marketBaseVault,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+          ...accounts,
+        }),
+      crankTwap: (accounts) =>
+        openbookTwap.methods.crankTwap().accounts(accounts),
+      consumeEvents: (accounts) =>
+        openbookTwap.methods.consumeEvents(new BN(10)).accounts({
+          eventHeap: a.storedMarket.eventHeap,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+          ...accounts,
+        }),
+    };
+
+    for (const build of Object.values(builders)) {
+      // `a`'s TWAP market paired with `b`'s market and book
+      await expectError(
+        build({
+          twapMarket: a.twapMarket,
+          market: b.market,
+          bids: b.storedMarket.bids,
+          asks: b.storedMarket.asks,
+        }).rpc(),
+        "InvalidMarket"
+      );
+
+      // `a`'s market paired with `b`'s bids
+      await expectError(
+        build({
+          twapMarket: a.twapMarket,
+          market: a.market,
+          bids: b.storedMarket.bids,
+          asks: a.storedMarket.asks,
+        }).rpc(),
+        "InvalidBids"
+      );
+
+      // `a`'s market paired with `b`'s asks
+      await expectError(
+        build({
+          twapMarket: a.twapMarket,
+          market: a.market,
+          bids: a.storedMarket.bids,
+          asks: b.storedMarket.asks,
+        }).rpc(),
+        "InvalidAsks"
+      );
+    }
+
+    // `settle_funds_expired` doesn't take the book, so only check the market
+    await expectError(
+      openbookTwap.methods
+        .settleFundsExpired()
+        .accounts({
+          twapMarket: a.twapMarket,
+          openOrdersAccount,
+          market: b.market,
+          marketAuthority: b.storedMarket.marketAuthority,
+          marketBaseVault: b.storedMarket.marketBaseVault,
+          marketQuoteVault: b.storedMarket.marketQuoteVault,
+          userBaseAccount: metaAccount,
+          userQuoteAccount: usdcAccount,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+        })
+        .rpc(),
+      "InvalidMarket"
+    );
+  });
+
+  it("Transfers and renounces the close authority", async () => {
+    let mintAuthority = Keypair.generate();
+    let META = await createMint(
+      banksClient,
+      payer,
+      mintAuthority.publicKey,
+      null,
+      META_DECIMALS
+    );
+    let USDC = await createMint(
+      banksClient,
+      payer,
+      mintAuthority.publicKey,
+      null,
+      USDC_DECIMALS
+    );
+
+    const { twapMarket } = await createTwapMarket(META, USDC);
+
+    let storedTwapMarket = await openbookTwap.account.twapMarket.fetch(
+      twapMarket
+    );
+    assert.ok(storedTwapMarket.closeAuthority.equals(payer.publicKey));
+
+    const newCloseAuthority = Keypair.generate();
+
+    await openbookTwap.methods
+      .setCloseAuthority(newCloseAuthority.publicKey)
+      .accounts({ twapMarket, closeAuthority: payer.publicKey })
+      .rpc();
+
+    // the payer is no longer the close authority
+    await expectError(
+      openbookTwap.methods
+        .setCloseAuthority(payer.publicKey)
+        .accounts({ twapMarket, closeAuthority: payer.publicKey })
+        .rpc(),
+      "InvalidCloseAuthority"
+    );
+
+    await openbookTwap.methods
+      .setCloseAuthority(null)
+      .accounts({ twapMarket, closeAuthority: newCloseAuthority.publicKey })
+      .signers([newCloseAuthority])
+      .rpc();
+
+    storedTwapMarket = await openbookTwap.account.twapMarket.fetch(twapMarket);
+    assert.isNull(storedTwapMarket.closeAuthority);
+  });
+
+  it("Migrates a v0.2 TWAP market", async () => {
+    const market = Keypair.generate().publicKey;
+    const [twapMarket] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("twap_market"), market.toBuffer()],
+      openbookTwap.programId
+    );
+
+    // a TWAP market as v0.2 encoded it, padded to the space it allocated
+    const data = Buffer.alloc(144);
+    let offset = 0;
+    anchor.BorshAccountsCoder.accountDiscriminator("TWAPMarket").copy(data);
+    offset += 8;
+    market.toBuffer().copy(data, offset);
+    offset += 32;
+    data.writeUInt8(254, offset); // pda_bump
+    offset += 1;
+    for (const value of [
+      EXPECTED_VALUE, // expected_value
+      100, // initial_slot
+      150, // last_updated_slot
+      160, // last_observed_slot
+      EXPECTED_VALUE, // last_observation
+    ]) {
+      data.writeBigUInt64LE(BigInt(value), offset);
+      offset += 8;
+    }
+    // observation_aggregator, a u128
+    data.writeBigUInt64LE(BigInt(EXPECTED_VALUE * 51), offset);
+    offset += 16;
+    data.writeBigUInt64LE(BigInt(MAX_UPDATE_LOTS), offset);
+    offset += 8;
+    payer.publicKey.toBuffer().copy(data, offset);
+
+    const rent = await banksClient.getRent();
+    context.setAccount(twapMarket, {
+      lamports: Number(rent.minimumBalance(BigInt(data.length))),
+      data,
+      owner: openbookTwap.programId,
+      executable: false,
+    });
+
+    await openbookTwap.methods
+      .migrateTwapMarket()
+      .accounts({ twapMarket, payer: payer.publicKey })
+      .rpc();
+
+    const storedTwapMarket = await openbookTwap.account.twapMarket.fetch(
+      twapMarket
+    );
+    const oracle = storedTwapMarket.twapOracle;
+    assert.strictEqual(storedTwapMarket.version, 1);
+    assert.ok(storedTwapMarket.market.equals(market));
+    assert.strictEqual(storedTwapMarket.pdaBump, 254);
+    assert.ok(storedTwapMarket.closeMarketRentReceiver.equals(payer.publicKey));
+    assert.isNull(storedTwapMarket.closeAuthority);
+    assert.strictEqual(oracle.initialSlot.toNumber(), 100);
+    assert.strictEqual(oracle.lastObservation.toNumber(), EXPECTED_VALUE);
+    assert.strictEqual(
+      oracle.observationAggregator.toNumber(),
+      EXPECTED_VALUE * 51
+    );
+    assert.strictEqual(
+      oracle.maxObservationChangePerUpdateLots.toNumber(),
+      MAX_UPDATE_LOTS
+    );
+    assert.strictEqual(oracle.numCheckpoints.toNumber(), 1);
+
+    await expectError(
+      openbookTwap.methods
+        .migrateTwapMarket()
+        .accounts({ twapMarket, payer: payer.publicKey })
+        .rpc(),
+      "TWAPMarketAlreadyMigrated"
+    );
+  });
+
+  it("Resolves a decision between two TWAP markets", async () => {
+    let mintAuthority = Keypair.generate();
+    let META = await createMint(
+      banksClient,
+      payer,
+      mintAuthority.publicKey,
+      null,
+      META_DECIMALS
+    );
+    let USDC = await createMint(
+      banksClient,
+      payer,
+      mintAuthority.publicKey,
+      null,
+      USDC_DECIMALS
+    );
+    let usdcAccount = await createAccount(
+      banksClient,
+      payer,
+      USDC,
+      payer.publicKey
+    );
+    let metaAccount = await createAccount(
+      banksClient,
+      payer,
+      META,
+      payer.publicKey
+    );
+    await mintTo(
+      banksClient,
+      payer,
+      META,
+      metaAccount,
+      mintAuthority,
+      META_AMOUNT_SCALED
+    );
+    await mintTo(
+      banksClient,
+      payer,
+      USDC,
+      usdcAccount,
+      mintAuthority,
+      USDC_AMOUNT_SCALED
+    );
+
+    const clock = await banksClient.getClock();
+    const startSlot = new BN(clock.slot.toString());
+    const endSlot = startSlot.addn(12);
+
+    // a decision's markets must end, so that their history can't be
+    // overwritten before it's resolved
+    const marketArgs = { ...CREATE_TWAP_MARKET_ARGS, endSlot };
+    const pass = await createTwapMarket(META, USDC, marketArgs);
+    const fail = await createTwapMarket(META, USDC, marketArgs);
+
+    // quote the pass market around $60 and the fail market around $40
+    for (const [{ market, twapMarket, storedMarket }, price] of [
+      [pass, 60],
+      [fail, 40],
+    ] as const) {
+      const openOrdersAccount = await openbook.createOpenOrders(
+        payer,
+        market,
+        "decision"
+      );
+      for (const [side, priceLots] of [
+        [Side.Bid, (price - 1) * 10_000],
+        [Side.Ask, (price + 1) * 10_000],
+      ]) {
+        await openbookTwap.methods
+          .placeOrder({
+            side,
+            priceLots: new BN(priceLots),
+            maxBaseLots: new BN(1),
+            maxQuoteLotsIncludingFees: new BN(priceLots),
+            clientOrderId: new BN(1),
+            orderType: OrderType.Limit,
+            expiryTimestamp: new BN(0),
+            selfTradeBehavior: SelfTradeBehavior.DecrementTake,
+            limit: 255,
+          })
+          .accounts({
+            signer: payer.publicKey,
+            asks: storedMarket.asks,
+            bids: storedMarket.bids,
+            marketVault:
+              side === Side.Bid
+                ? storedMarket.marketQuoteVault
+                : storedMarket.marketBaseVault,
+            eventHeap: storedMarket.eventHeap,
+            market,
+            openOrdersAccount,
+            userTokenAccount: side === Side.Bid ? usdcAccount : metaAccount,
+            twapMarket,
+            openbookProgram: OPENBOOK_PROGRAM_ID,
+          })
+          .rpc();
+      }
+    }
+
+    const decisionPairFor = (passTwapMarket, failTwapMarket) =>
+      PublicKey.findProgramAddressSync(
+        [
+          anchor.utils.bytes.utf8.encode("decision_pair"),
+          payer.publicKey.toBuffer(),
+          passTwapMarket.toBuffer(),
+          failTwapMarket.toBuffer(),
+        ],
+        openbookTwap.programId
+      )[0];
+
+    const createDecisionPair = (
+      passTwapMarket,
+      failTwapMarket,
+      end = endSlot,
+      threshold = 100
+    ) =>
+      openbookTwap.methods
+        .createDecisionPair(startSlot, end, new BN(threshold))
+        .accounts({
+          decisionPair: decisionPairFor(passTwapMarket, failTwapMarket),
+          passTwapMarket,
+          failTwapMarket,
+          authority: payer.publicKey,
+          payer: payer.publicKey,
+        })
+        .rpc();
+
+    await expectError(
+      createDecisionPair(pass.twapMarket, fail.twapMarket, endSlot, 10_001),
+      "InvalidPassThreshold"
+    );
+    await expectError(
+      createDecisionPair(pass.twapMarket, fail.twapMarket, endSlot.addn(1)),
+      "InvalidDecisionWindow"
+    );
+
+    // one pair for each way round, so that one passes and the other fails
+    await createDecisionPair(pass.twapMarket, fail.twapMarket);
+    await createDecisionPair(fail.twapMarket, pass.twapMarket);
+
+    const resolve = (passTwapMarket, failTwapMarket) =>
+      openbookTwap.methods
+        .resolveDecision()
+        .accounts({
+          decisionPair: decisionPairFor(passTwapMarket, failTwapMarket),
+          passTwapMarket,
+          failTwapMarket,
+        })
+        .rpc();
+
+    await expectError(
+      resolve(pass.twapMarket, fail.twapMarket),
+      "DecisionWindowNotOver"
+    );
+
+    for (let i = 0; i < 12; i++) {
+      const storedClock = await banksClient.getClock();
+      context.setClock(
+        new Clock(
+          storedClock.slot + BigInt(1),
+          storedClock.epochStartTimestamp,
+          storedClock.epoch,
+          storedClock.leaderScheduleEpoch,
+          storedClock.unixTimestamp
+        )
+      );
+      for (const { market, twapMarket, storedMarket } of [pass, fail]) {
+        await openbookTwap.methods
+          .crankTwap()
+          .accounts({
+            twapMarket,
+            market,
+            bids: storedMarket.bids,
+            asks: storedMarket.asks,
+          })
+          .rpc();
+      }
+    }
+
+    const storedClock = await banksClient.getClock();
+    context.setClock(
+      new Clock(
+        storedClock.slot + BigInt(1),
+        storedClock.epochStartTimestamp,
+        storedClock.epoch,
+        storedClock.leaderScheduleEpoch,
+        storedClock.unixTimestamp
+      )
+    );
+
+    // the markets' rent can't be reclaimed while a decision depends on them
+    await openbookTwap.methods
+      .finalizeTwap()
+      .accounts({ twapMarket: pass.twapMarket })
+      .rpc();
+    await expectError(
+      openbookTwap.methods
+        .closeMarket()
+        .accounts({
+          closeMarketRentReceiver: payer.publicKey,
+          closeAuthority: payer.publicKey,
+          twapMarket: pass.twapMarket,
+          market: pass.market,
+          bids: pass.storedMarket.bids,
+          asks: pass.storedMarket.asks,
+          eventHeap: pass.storedMarket.eventHeap,
+          openbookProgram: OPENBOOK_PROGRAM_ID,
+        })
+        .rpc(),
+      "UnresolvedDecisions"
+    );
+
+    await resolve(pass.twapMarket, fail.twapMarket);
+    await resolve(fail.twapMarket, pass.twapMarket);
+
+    let storedDecisionPair = await openbookTwap.account.decisionPair.fetch(
+      decisionPairFor(pass.twapMarket, fail.twapMarket)
+    );
+    const passTwap = storedDecisionPair.passTwap.toNumber();
+    const failTwap = storedDecisionPair.failTwap.toNumber();
+    assert.isAbove(passTwap, EXPECTED_VALUE);
+    assert.isBelow(failTwap, EXPECTED_VALUE);
+    assert.deepEqual(storedDecisionPair.outcome, { pass: {} });
+
+    storedDecisionPair = await openbookTwap.account.decisionPair.fetch(
+      decisionPairFor(fail.twapMarket, pass.twapMarket)
+    );
+    assert.strictEqual(storedDecisionPair.passTwap.toNumber(), failTwap);
+    assert.strictEqual(storedDecisionPair.failTwap.toNumber(), passTwap);
+    assert.deepEqual(storedDecisionPair.outcome, { fail: {} });
+
+    await expectError(
+      resolve(pass.twapMarket, fail.twapMarket),
+      "DecisionAlreadyResolved"
+    );
+
+    const storedTwapMarket = await openbookTwap.account.twapMarket.fetch(
+      pass.twapMarket
+    );
+    assert.strictEqual(storedTwapMarket.unresolvedDecisions.toNumber(), 0);
+
+    // resolved pairs can be closed for their rent
+    await openbookTwap.methods
+      .closeDecisionPair()
+      .accounts({
+        decisionPair: decisionPairFor(pass.twapMarket, fail.twapMarket),
+        authority: payer.publicKey,
+      })
+      .rpc();
+    assert.isNull(
+      await banksClient.getAccount(
+        decisionPairFor(pass.twapMarket, fail.twapMarket)
+      )
+    );
   });
 });